@@ -0,0 +1,121 @@
+use crate::camera::Camera;
+use crate::objects::HittableList;
+use crate::output::save_image;
+use crate::renderer::{seed_for_frame, Renderer};
+use image::ImageResult;
+use std::path::{Path, PathBuf};
+
+// A numbered frame sequence, e.g. a turntable or a camera flythrough.
+// `scene` gives the camera and world of each frame.
+pub struct Animation<F> {
+    pub frames: u32,
+    scene: F,
+}
+
+impl<F: Fn(u32) -> (Camera, HittableList)> Animation<F> {
+    pub fn new(frames: u32, scene: F) -> Animation<F> {
+        Animation { frames, scene }
+    }
+
+    // Writes frame_0000.png, frame_0001.png, ... into `directory`, swapping
+    // each frame's camera and world into `renderer` (with a BVH if
+    // settings.bvh is set). Frames get their own seeds derived from
+    // settings.seed, so the sequence is reproducible. Returns the written
+    // paths.
+    pub fn render<P: AsRef<Path>>(
+        &self,
+        renderer: &mut Renderer,
+        directory: P,
+    ) -> ImageResult<Vec<PathBuf>> {
+        let seed = renderer.settings.seed;
+        let mut paths = Vec::with_capacity(self.frames as usize);
+
+        for frame in 0..self.frames {
+            let (mut camera, world) = (self.scene)(frame);
+            camera.set_aspect_ratio(renderer.settings.aspect_ratio());
+            renderer.camera = camera;
+            renderer.set_world(world);
+            renderer.settings.seed = seed_for_frame(frame, seed);
+
+            let path = directory.as_ref().join(format!("frame_{:04}.png", frame));
+            let image = renderer.render();
+            renderer.settings.seed = seed;
+            save_image(&path, &image)?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraBuilder;
+    use crate::la::{Color, Point3};
+    use crate::objects::{Lambertian, Sphere};
+    use crate::renderer::RenderSettings;
+    use std::f64::consts::PI;
+    use std::fs;
+    use std::sync::Arc;
+
+    #[test]
+    fn turntable_frames_are_separate_files_that_differ_at_the_center() {
+        // A red, a green and a blue sphere around the origin, and a camera
+        // circling outside them, facing each in turn
+        let colors = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+        ];
+        let around = |frame: u32, radius: f64| {
+            let angle = 2.0 * PI * frame as f64 / 3.0;
+            Point3::new(radius * angle.cos(), 0.0, radius * angle.sin())
+        };
+        let scene = |frame| {
+            let mut world = HittableList::new();
+            for (i, color) in colors.iter().enumerate() {
+                let mat = Arc::new(Lambertian::new(*color));
+                world.add(Arc::new(Sphere::new(around(i as u32, 1.0), 0.4, mat)));
+            }
+            let camera = CameraBuilder::new(around(frame, 4.0), Point3::new(0.0, 0.0, 0.0))
+                .v_fov(30.0)
+                .build();
+            (camera, world)
+        };
+        let animation = Animation::new(3, scene);
+
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 9,
+            samples_per_pixel: 4,
+            min_samples_per_pixel: 4,
+            max_depth: 4,
+            ..RenderSettings::default()
+        };
+        let (camera, world) = scene(0);
+        let mut renderer = Renderer::new(camera, Arc::new(world), settings);
+        renderer.show_progress = false;
+
+        let directory =
+            std::env::temp_dir().join(format!("ray_tracing_turntable_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let paths = animation.render(&mut renderer, &directory).unwrap();
+        let centers: Vec<_> = paths
+            .iter()
+            .map(|path| *image::open(path).unwrap().to_rgb8().get_pixel(8, 4))
+            .collect();
+        fs::remove_dir_all(&directory).unwrap();
+
+        let names: Vec<_> = paths.iter().map(|path| path.file_name().unwrap()).collect();
+        assert_eq!(
+            names,
+            ["frame_0000.png", "frame_0001.png", "frame_0002.png"]
+        );
+        // Each frame's center is dominated by the color of the sphere in front
+        for (frame, center) in centers.iter().enumerate() {
+            let brightest = (0..3).max_by_key(|&c| center.0[c]).unwrap();
+            assert_eq!(brightest, frame, "{:?}", centers);
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crate::hdr::HdrImage;
+use crate::la::{Color, Vec3};
+use crate::pdf::Pdf;
+use image::codecs::hdr::HdrDecoder;
+use image::ImageResult;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+// Color seen by rays that miss everything in the world, `dir` is a unit vector
+pub trait Background: Send + Sync {
+    fn color(&self, dir: &Vec3) -> Color;
+
+    // Directions favoring the bright parts, so bounces can be aimed at them.
+    // Without one the background is only found by chance.
+    fn pdf(&self) -> Option<&dyn Pdf> {
+        None
+    }
+}
+
+// A plain color, e.g. black for scenes lit only by emitters
+impl Background for Color {
+    fn color(&self, dir: &Vec3) -> Color {
+        *self
+    }
+}
+
+// The default white to light blue sky, blended along y
+pub struct Sky;
+
+impl Background for Sky {
+    fn color(&self, dir: &Vec3) -> Color {
+        let t = 0.5 * (dir.y() + 1.0);
+        Color::new(1.0, 1.0, 1.0).lerp(&Color::new(0.5, 0.7, 1.0), t)
+    }
+}
+
+// Blends from `bottom` to `top` along `axis`: rays pointing along the axis
+// see `top`, rays pointing against it see `bottom`
+pub struct Gradient {
+    pub top: Color,
+    pub bottom: Color,
+    pub axis: Vec3,
+}
+
+impl Gradient {
+    pub fn new(top: Color, bottom: Color, axis: Vec3) -> Gradient {
+        Gradient {
+            top,
+            bottom,
+            axis: axis.as_unit_vector(),
+        }
+    }
+
+    // Blended along +y, like Sky
+    pub fn vertical(top: Color, bottom: Color) -> Gradient {
+        Gradient::new(top, bottom, Vec3::new(0.0, 1.0, 0.0))
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, dir: &Vec3) -> Color {
+        let t = 0.5 * (Vec3::dot(dir, &self.axis) + 1.0);
+        self.bottom.lerp(&self.top, t.clamp(0.0, 1.0))
+    }
+}
+
+// An equirectangular image around the scene. The middle column faces +x,
+// the top row is straight up (+y), and the left/right edges meet at -x.
+pub struct EnvironmentMap {
+    image: HdrImage,
+    distribution: EnvironmentPdf,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: HdrImage) -> EnvironmentMap {
+        let distribution = EnvironmentPdf::new(&image);
+        EnvironmentMap {
+            image,
+            distribution,
+        }
+    }
+
+    // Radiance .hdr files keep their full range, other formats are read as
+    // 8-bit colors scaled to [0, 1]
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<EnvironmentMap> {
+        let path = path.as_ref();
+        let is_hdr = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("hdr"));
+
+        let image = if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let (width, height) = (decoder.metadata().width, decoder.metadata().height);
+            let pixels = decoder.read_image_hdr()?;
+            let mut image = HdrImage::new(width, height);
+            image
+                .pixels_mut()
+                .iter_mut()
+                .zip(pixels)
+                .for_each(|(out, pixel)| *out = pixel.0);
+            image
+        } else {
+            let rgb = image::open(path)?.into_rgb32f();
+            let mut image = HdrImage::new(rgb.width(), rgb.height());
+            image
+                .pixels_mut()
+                .iter_mut()
+                .zip(rgb.pixels())
+                .for_each(|(out, pixel)| *out = pixel.0);
+            image
+        };
+
+        Ok(EnvironmentMap::new(image))
+    }
+
+    // Nearest pixel in direction `dir`
+    pub fn sample(&self, dir: &Vec3) -> Color {
+        let (width, height) = (self.image.width(), self.image.height());
+        if width == 0 || height == 0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let (x, y) = pixel_at(dir, width, height);
+        self.image.get_pixel(x, y)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, dir: &Vec3) -> Color {
+        self.sample(dir)
+    }
+
+    fn pdf(&self) -> Option<&dyn Pdf> {
+        Some(&self.distribution)
+    }
+}
+
+// The pixel of a width x height equirectangular image seen in direction `dir`
+fn pixel_at(dir: &Vec3, width: u32, height: u32) -> (u32, u32) {
+    let (theta, phi) = dir.to_spherical();
+    let u = (phi + PI) / (2.0 * PI);
+    let v = theta / PI;
+    let x = ((u * width as f64) as u32).min(width - 1);
+    let y = ((v * height as f64) as u32).min(height - 1);
+    (x, y)
+}
+
+// Picks environment map pixels in proportion to the light they send in,
+// luminance times the solid angle they cover, then a uniform direction
+// within the pixel. Rows are picked first from their totals, then a pixel
+// within the row.
+pub struct EnvironmentPdf {
+    width: u32,
+    height: u32,
+    // Cumulative row probabilities, ending at 1
+    rows: Vec<f64>,
+    // Cumulative probabilities within each row, `width` per row and each
+    // ending at 1
+    columns: Vec<f64>,
+}
+
+impl EnvironmentPdf {
+    pub fn new(image: &HdrImage) -> EnvironmentPdf {
+        let (width, height) = (image.width(), image.height());
+        let mut rows = Vec::with_capacity(height as usize);
+        let mut columns = Vec::with_capacity(width as usize * height as usize);
+
+        let mut total = 0.0;
+        for y in 0..height {
+            // Rows towards the poles are squeezed into less solid angle
+            let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+            let row_start = columns.len();
+            let mut row_total = 0.0;
+            for x in 0..width {
+                row_total += image.get_pixel(x, y).luminance().max(0.0) * sin_theta;
+                columns.push(row_total);
+            }
+            normalize_cdf(&mut columns[row_start..], row_total);
+            total += row_total;
+            rows.push(total);
+        }
+        normalize_cdf(&mut rows, total);
+
+        EnvironmentPdf {
+            width,
+            height,
+            rows,
+            columns,
+        }
+    }
+
+    fn row(&self, y: u32) -> &[f64] {
+        let start = (y * self.width) as usize;
+        &self.columns[start..start + self.width as usize]
+    }
+}
+
+// Divides running sums by their total, or makes them uniform if it's zero
+fn normalize_cdf(cdf: &mut [f64], total: f64) {
+    let n = cdf.len();
+    for (i, c) in cdf.iter_mut().enumerate() {
+        *c = if total > 0.0 {
+            *c / total
+        } else {
+            (i + 1) as f64 / n as f64
+        };
+    }
+}
+
+// Probability of entry `i` of a normalized CDF, and sampling one from it
+fn cdf_probability(cdf: &[f64], i: usize) -> f64 {
+    cdf[i] - if i > 0 { cdf[i - 1] } else { 0.0 }
+}
+
+fn cdf_sample(cdf: &[f64], r: f64) -> usize {
+    cdf.partition_point(|&c| c <= r).min(cdf.len() - 1)
+}
+
+impl Pdf for EnvironmentPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        if self.width == 0 || self.height == 0 {
+            return 0.0;
+        }
+
+        let (x, y) = pixel_at(&direction.as_unit_vector(), self.width, self.height);
+        let probability =
+            cdf_probability(&self.rows, y as usize) * cdf_probability(self.row(y), x as usize);
+
+        // A pixel covers (2 pi / width) * (pi / height) * sin(theta) steradians
+        let (theta, _) = direction.to_spherical();
+        let sin_theta = theta.sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        probability * self.width as f64 * self.height as f64 / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        if self.width == 0 || self.height == 0 {
+            return Vec3::rand_unit_vector(rng);
+        }
+
+        let y = cdf_sample(&self.rows, rng.gen());
+        let x = cdf_sample(self.row(y as u32), rng.gen());
+        let u = (x as f64 + rng.gen::<f64>()) / self.width as f64;
+        let v = (y as f64 + rng.gen::<f64>()) / self.height as f64;
+        Vec3::from_spherical(v * PI, u * 2.0 * PI - PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::assert_vec3_eq;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    // 4x2 pixels, each a different color
+    fn test_environment() -> EnvironmentMap {
+        let mut image = HdrImage::new(4, 2);
+        for y in 0..2 {
+            for x in 0..4 {
+                image.put_pixel(x, y, Color::new(x as f64, y as f64, 0.5));
+            }
+        }
+        EnvironmentMap::new(image)
+    }
+
+    #[test]
+    fn direction_through_a_pixel_center_returns_that_pixel() {
+        let environment = test_environment();
+        for y in 0..2 {
+            for x in 0..4 {
+                let phi = 2.0 * PI * (x as f64 + 0.5) / 4.0 - PI;
+                let theta = PI * (y as f64 + 0.5) / 2.0;
+                let dir = Vec3::from_spherical(theta, phi);
+                assert_eq!(
+                    environment.sample(&dir),
+                    Color::new(x as f64, y as f64, 0.5)
+                );
+            }
+        }
+
+        // Straight up is the top row, +x the middle column
+        assert_eq!(environment.sample(&Vec3::new(0.1, 1.0, 0.0)).y(), 0.0);
+        assert_eq!(environment.sample(&Vec3::new(1.0, 0.1, 0.0)).x(), 2.0);
+    }
+
+    #[test]
+    fn gradient_is_its_top_color_up_the_axis_and_bottom_color_down() {
+        let top = Color::new(1.0, 0.5, 0.2);
+        let bottom = Color::new(0.1, 0.2, 0.6);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let vertical = Gradient::vertical(top, bottom);
+        assert_vec3_eq!(vertical.color(&up), top, 1e-12);
+        assert_vec3_eq!(vertical.color(&-up), bottom, 1e-12);
+
+        let sideways = Gradient::new(top, bottom, Vec3::new(2.0, 0.0, 0.0));
+        assert_vec3_eq!(sideways.color(&Vec3::new(1.0, 0.0, 0.0)), top, 1e-12);
+        assert_vec3_eq!(sideways.color(&up), top.lerp(&bottom, 0.5), 1e-12);
+    }
+
+    #[test]
+    fn environment_samples_concentrate_on_a_bright_spot() {
+        let mut image = HdrImage::new(32, 16);
+        for y in 0..16 {
+            for x in 0..32 {
+                image.put_pixel(x, y, Color::new(0.1, 0.1, 0.1));
+            }
+        }
+        image.put_pixel(24, 7, Color::new(100.0, 100.0, 100.0));
+        let pdf = EnvironmentPdf::new(&image);
+
+        // The spot is 1 of 512 pixels but sends in most of the light
+        let mut rng = SmallRng::seed_from_u64(16);
+        let n = 10_000;
+        let in_spot = (0..n)
+            .filter(|_| pixel_at(&pdf.generate(&mut rng), 32, 16) == (24, 7))
+            .count();
+        assert!(in_spot as f64 / n as f64 > 0.5, "{}", in_spot);
+
+        let theta = PI * 7.5 / 16.0;
+        let phi = 2.0 * PI * 24.5 / 32.0 - PI;
+        let toward_spot = Vec3::from_spherical(theta, phi);
+        assert!(pdf.value(&toward_spot) > 100.0 / (4.0 * PI));
+        assert!(pdf.value(&-toward_spot) < 1.0 / (4.0 * PI));
+    }
+}
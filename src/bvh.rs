@@ -0,0 +1,385 @@
+use crate::aabb::Aabb;
+use crate::la::{Point3, Ray, Vec3};
+use crate::objects::{HitRecord, Hittable, HittableList};
+use rand::{Rng, RngCore};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+// Nodes with fewer objects than this are built on the current thread, where
+// splitting the work would cost more than it saves
+const PARALLEL_BUILD_THRESHOLD: usize = 1024;
+
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    // None only at a root holding unbounded objects, which are always tested,
+    // or one built over nothing, which never hits
+    bbox: Option<Aabb>,
+}
+
+impl BvhNode {
+    // Unbounded objects (planes...) can't be partitioned, so they're kept in a
+    // plain list next to the tree built over everything else
+    pub fn new(objects: &mut [Arc<dyn Hittable>]) -> BvhNode {
+        BvhNode::with_split(objects, Split::Median)
+    }
+
+    // Slower to build, but picks each split by the surface area heuristic,
+    // which makes for much cheaper traversal when objects are clustered
+    pub fn new_sah(objects: &mut [Arc<dyn Hittable>]) -> BvhNode {
+        BvhNode::with_split(objects, Split::Sah)
+    }
+
+    fn with_split(objects: &mut [Arc<dyn Hittable>], split: Split) -> BvhNode {
+        if objects.is_empty() {
+            return BvhNode {
+                left: Arc::new(HittableList::new()),
+                right: Arc::new(HittableList::new()),
+                bbox: None,
+            };
+        }
+
+        let (mut bounded, unbounded): (Vec<_>, Vec<_>) = objects
+            .iter()
+            .cloned()
+            .partition(|object| object.bounding_box().is_some());
+        if unbounded.is_empty() {
+            return BvhNode::build(objects, split);
+        }
+
+        let mut unbounded_list = HittableList::new();
+        for object in unbounded {
+            unbounded_list.add(object);
+        }
+        let left: Arc<dyn Hittable> = if bounded.is_empty() {
+            Arc::new(HittableList::new())
+        } else {
+            Arc::new(BvhNode::build(&mut bounded, split))
+        };
+
+        BvhNode {
+            left,
+            right: Arc::new(unbounded_list),
+            bbox: None,
+        }
+    }
+
+    fn build(objects: &mut [Arc<dyn Hittable>], split: Split) -> BvhNode {
+        let bbox = objects
+            .iter()
+            .map(BvhNode::box_of)
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+            .unwrap();
+
+        // Both sorts are stable, so the tree doesn't depend on the thread count
+        let parallel = objects.len() >= PARALLEL_BUILD_THRESHOLD;
+        let mid = match split {
+            Split::Sah => BvhNode::sah_split(objects, parallel),
+            Split::Median => None,
+        };
+        // Otherwise split at the median centroid along the longest axis
+        let mid = mid.unwrap_or_else(|| {
+            BvhNode::sort_along(objects, bbox.longest_axis(), parallel);
+            objects.len() / 2
+        });
+
+        let (left, right): (Arc<dyn Hittable>, Arc<dyn Hittable>) = match objects.len() {
+            1 => (objects[0].clone(), objects[0].clone()),
+            2 => (objects[0].clone(), objects[1].clone()),
+            _ => {
+                let (lo, hi) = objects.split_at_mut(mid);
+                let (lo, hi) = if parallel {
+                    rayon::join(|| BvhNode::build(lo, split), || BvhNode::build(hi, split))
+                } else {
+                    (BvhNode::build(lo, split), BvhNode::build(hi, split))
+                };
+                (Arc::new(lo), Arc::new(hi))
+            }
+        };
+
+        BvhNode {
+            left,
+            right,
+            bbox: Some(bbox),
+        }
+    }
+
+    // Tries every split between neighbours along each axis, costing a split as
+    // the children's surface areas weighted by their object counts. Leaves
+    // the objects sorted along the winning axis and returns the split index,
+    // or None when there's nothing to tell the objects apart by.
+    fn sah_split(objects: &mut [Arc<dyn Hittable>], parallel: bool) -> Option<usize> {
+        let n = objects.len();
+        if n <= 2 {
+            return None;
+        }
+
+        // All centroids in one spot: every split costs the same
+        let centroid_bounds = objects
+            .iter()
+            .map(|object| {
+                let c = BvhNode::box_of(object).centroid();
+                Aabb::new(c, c)
+            })
+            .reduce(|a, b| Aabb::surrounding(&a, &b))?;
+        if (centroid_bounds.max - centroid_bounds.min).component_max() <= 0.0 {
+            return None;
+        }
+
+        // Each axis sorts the same starting order, so re-sorting the winner
+        // reproduces the order its cost was measured on
+        let original = objects.to_vec();
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            objects.clone_from_slice(&original);
+            BvhNode::sort_along(objects, axis, parallel);
+            let boxes: Vec<Aabb> = objects.iter().map(BvhNode::box_of).collect();
+
+            // right_areas[i] is the area of the box around boxes[i..]
+            let mut right_areas = vec![0.0; n];
+            let mut right = boxes[n - 1];
+            for i in (1..n).rev() {
+                right = Aabb::surrounding(&right, &boxes[i]);
+                right_areas[i] = right.surface_area();
+            }
+
+            let mut left = boxes[0];
+            for i in 1..n {
+                let cost = left.surface_area() * i as f64 + right_areas[i] * (n - i) as f64;
+                if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                    best = Some((cost, axis, i));
+                }
+                left = Aabb::surrounding(&left, &boxes[i]);
+            }
+        }
+
+        let (cost, axis, mid) = best?;
+        if !cost.is_finite() {
+            return None;
+        }
+        objects.clone_from_slice(&original);
+        BvhNode::sort_along(objects, axis, parallel);
+        Some(mid)
+    }
+
+    fn sort_along(objects: &mut [Arc<dyn Hittable>], axis: usize, parallel: bool) {
+        let by_centroid = |a: &Arc<dyn Hittable>, b: &Arc<dyn Hittable>| {
+            let ca = BvhNode::box_of(a).centroid()[axis];
+            let cb = BvhNode::box_of(b).centroid()[axis];
+            ca.total_cmp(&cb)
+        };
+        if parallel {
+            objects.par_sort_by(by_centroid);
+        } else {
+            objects.sort_by(by_centroid);
+        }
+    }
+
+    pub fn from_list(list: &HittableList) -> BvhNode {
+        BvhNode::new(&mut list.objects().to_vec())
+    }
+
+    fn box_of(object: &Arc<dyn Hittable>) -> Aabb {
+        object
+            .bounding_box()
+            .expect("unbounded objects are kept out of the tree")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Split {
+    Median,
+    Sah,
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if let Some(bbox) = &self.bbox {
+            if !bbox.hit(r, t_min, t_max) {
+                return None;
+            }
+        }
+
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let closest = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+        let hit_right = self.right.hit(r, t_min, closest);
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn can_sample(&self) -> bool {
+        self.left.can_sample() || self.right.can_sample()
+    }
+
+    // Picks either child with equal probability when both can be sampled,
+    // so the density is their average
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        match (self.left.can_sample(), self.right.can_sample()) {
+            (true, true) => {
+                0.5 * self.left.pdf_value(origin, direction)
+                    + 0.5 * self.right.pdf_value(origin, direction)
+            }
+            (true, false) => self.left.pdf_value(origin, direction),
+            (false, true) => self.right.pdf_value(origin, direction),
+            (false, false) => 0.0,
+        }
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let left = match (self.left.can_sample(), self.right.can_sample()) {
+            (true, true) => rng.gen_bool(0.5),
+            (left, _) => left,
+        };
+        if left {
+            self.left.random(origin, rng)
+        } else {
+            self.right.random(origin, rng)
+        }
+    }
+
+    // This node's box, then both children's if the ray got through it
+    fn box_hits(&self, r: &Ray, t_min: f64, t_max: f64) -> u32 {
+        if let Some(bbox) = &self.bbox {
+            if !bbox.hit(r, t_min, t_max) {
+                return 0;
+            }
+        }
+
+        1 + self.left.box_hits(r, t_min, t_max) + self.right.box_hits(r, t_min, t_max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::{Color, Point3, Vec3};
+    use crate::objects::{Lambertian, Material, Sphere};
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn random_spheres(count: usize, rng: &mut SmallRng) -> HittableList {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for _ in 0..count {
+            let center = Vec3::rand(rng, -10.0, 10.0);
+            let radius = rng.gen_range(0.2..1.5);
+            list.add(Arc::new(Sphere::new(center, radius, mat.clone())));
+        }
+        list
+    }
+
+    fn random_rays(count: usize, rng: &mut SmallRng) -> Vec<Ray> {
+        (0..count)
+            .map(|_| Ray::new(Vec3::rand(rng, -15.0, 15.0), Vec3::rand_unit_vector(rng)))
+            .collect()
+    }
+
+    // Same object hit at the same distance, or both missing
+    fn assert_same_hits(a: &dyn Hittable, b: &dyn Hittable, rays: &[Ray]) {
+        for r in rays {
+            let hit_a = a.hit(r, 0.001, f64::INFINITY).map(|rec| (rec.t, rec.p));
+            let hit_b = b.hit(r, 0.001, f64::INFINITY).map(|rec| (rec.t, rec.p));
+            assert_eq!(hit_a, hit_b, "ray {:?}", r);
+        }
+    }
+
+    #[test]
+    fn bvh_hits_match_list() {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let list = random_spheres(100, &mut rng);
+        let rays = random_rays(1000, &mut rng);
+
+        let bvh = BvhNode::from_list(&list);
+        assert_same_hits(&bvh, &list, &rays);
+        assert!(rays
+            .iter()
+            .any(|r| list.hit(r, 0.001, f64::INFINITY).is_some()));
+    }
+
+    #[test]
+    fn empty_bvh_never_hits() {
+        let bvh = BvhNode::from_list(&HittableList::new());
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(bvh.bounding_box().is_none());
+        assert!(bvh.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn parallel_build_matches_a_single_thread_build() {
+        let mut rng = SmallRng::seed_from_u64(6);
+        let list = random_spheres(10_000, &mut rng);
+        let rays = random_rays(1000, &mut rng);
+        let build_on = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| BvhNode::from_list(&list))
+        };
+
+        let serial = build_on(1);
+        let parallel = build_on(8);
+        assert_same_hits(&parallel, &serial, &rays);
+    }
+
+    // Counts how often the wrapped object is tested, shared across clones
+    struct CountingHits {
+        inner: Arc<dyn Hittable>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Hittable for CountingHits {
+        fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.hit(r, t_min, t_max)
+        }
+
+        fn bounding_box(&self) -> Option<Aabb> {
+            self.inner.bounding_box()
+        }
+    }
+
+    #[test]
+    fn sah_tests_fewer_objects_than_median_on_a_clustered_scene() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        // A tight cluster of small spheres with a few large ones far away
+        let mut objects: Vec<Arc<dyn Hittable>> = (0..500)
+            .map(|i| {
+                let (center, radius) = if i % 50 == 0 {
+                    (Vec3::rand(&mut rng, -100.0, 100.0), 5.0)
+                } else {
+                    (Vec3::rand(&mut rng, -1.0, 1.0), 0.05)
+                };
+                Arc::new(CountingHits {
+                    inner: Arc::new(Sphere::new(center, radius, mat.clone())),
+                    calls: calls.clone(),
+                }) as Arc<dyn Hittable>
+            })
+            .collect();
+        let rays: Vec<Ray> = (0..2000)
+            .map(|_| {
+                let origin = Vec3::rand(&mut rng, -100.0, 100.0);
+                let target = Vec3::rand(&mut rng, -100.0, 100.0);
+                Ray::new(origin, target - origin)
+            })
+            .collect();
+        let mut count_hit_tests = |bvh: &BvhNode| {
+            calls.store(0, Ordering::Relaxed);
+            for r in &rays {
+                bvh.hit(r, 0.001, f64::INFINITY);
+            }
+            calls.load(Ordering::Relaxed)
+        };
+
+        let median = count_hit_tests(&BvhNode::new(&mut objects.clone()));
+        let sah = count_hit_tests(&BvhNode::new_sah(&mut objects));
+        assert!(sah < median, "sah {} median {}", sah, median);
+    }
+}
@@ -1,14 +1,27 @@
 use crate::la::{Point3, Ray, Vec3};
+use rand::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Projection {
+    Perspective,
+    // All rays share the view direction, origins spread over the viewport
+    Orthographic,
+}
 
 pub struct Camera {
+    projection: Projection,
     origin: Point3,
     horizontal: Vec3,
     vertical: Vec3,
     lower_left_corner: Point3,
     lens_radius: f64,
+    // Aperture blade count, None for a round lens
+    blades: Option<u32>,
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    time0: f64,
+    time1: f64,
 }
 
 impl Camera {
@@ -35,28 +48,316 @@ impl Camera {
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
         let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
-        
+
         let lens_radius = aperture / 2.0;
 
         Camera {
+            projection: Projection::Perspective,
             origin,
             horizontal,
             vertical,
             lower_left_corner,
             lens_radius,
+            blades: None,
+            u,
+            v,
+            w,
+            time0: 0.0,
+            time1: 0.0,
+        }
+    }
+
+    // Opens the shutter over [time0, time1] for motion blur
+    pub fn with_shutter(mut self, time0: f64, time1: f64) -> Camera {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    // The viewport is `ortho_height` world units tall and centered on look_from
+    pub fn new_orthographic(
+        look_from: &Point3,
+        look_at: &Point3,
+        up: &Vec3,
+        ortho_height: f64,
+        aspect_ratio: f64,
+    ) -> Camera {
+        let w = (*look_from - *look_at).as_unit_vector();
+        let u = Vec3::cross(up, &w).as_unit_vector();
+        let v = Vec3::cross(&w, &u);
+
+        let origin = *look_from;
+        let horizontal = aspect_ratio * ortho_height * u;
+        let vertical = ortho_height * v;
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0;
+
+        Camera {
+            projection: Projection::Orthographic,
+            origin,
+            horizontal,
+            vertical,
+            lower_left_corner,
+            lens_radius: 0.0,
+            blades: None,
             u,
             v,
             w,
+            time0: 0.0,
+            time1: 0.0,
         }
     }
 
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn origin(&self) -> Point3 {
+        self.origin
+    }
+
+    // Width over height of the viewport
+    pub fn aspect_ratio(&self) -> f64 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    // Keeps the vertical extent and view center, widening or narrowing the
+    // viewport to match an image of this aspect ratio
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let center = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+        self.horizontal = aspect_ratio * self.vertical.length() * self.u;
+        self.lower_left_corner = center - self.horizontal / 2.0 - self.vertical / 2.0;
+    }
+
+    // Focus distance and framing are kept, only the lens size changes
+    pub fn set_aperture(&mut self, aperture: f64) {
+        self.lens_radius = aperture / 2.0;
+    }
+
+    // Lens samples come from a regular polygon with this many sides instead
+    // of a disk, giving polygonal bokeh. None is a round lens.
+    pub fn set_blades(&mut self, blades: Option<u32>) {
+        self.blades = blades;
+    }
+
+    // Lens and shutter samples come from the thread-local RNG, use
+    // get_ray_rng for reproducible rays
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::rand_in_unit_disk();
-        let offset = self.u * rd.x() + self.v * rd.y();
-        
-        Ray::new(
+        self.get_ray_rng(s, t, &mut rand::thread_rng())
+    }
+
+    pub fn get_ray_rng<R: Rng + ?Sized>(&self, s: f64, t: f64, rng: &mut R) -> Ray {
+        if self.projection == Projection::Orthographic {
+            return Ray::new_timed(
+                self.lower_left_corner + s * self.horizontal + t * self.vertical,
+                -self.w,
+                self.shutter_time(rng),
+            );
+        }
+
+        // A pinhole camera has no lens to sample
+        let offset = if self.lens_radius == 0.0 {
+            Vec3::new(0.0, 0.0, 0.0)
+        } else {
+            let rd = self.lens_radius
+                * match self.blades {
+                    Some(blades) => Vec3::rand_in_unit_polygon(blades, rng),
+                    None => Vec3::rand_in_unit_disk(rng),
+                };
+            self.u * rd.x() + self.v * rd.y()
+        };
+
+        Ray::new_timed(
             self.origin + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            self.shutter_time(rng),
         )
     }
+
+    // Shutter is open over [time0, time1]
+    fn shutter_time<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        if self.time0 < self.time1 {
+            rng.gen_range(self.time0..self.time1)
+        } else {
+            self.time0
+        }
+    }
+}
+
+// Named alternative to Camera::new. Unset fields default to up = +y, a 90
+// degree field of view, 16:9, a pinhole lens focused on look_at and a closed
+// shutter at time 0.
+pub struct CameraBuilder {
+    look_from: Point3,
+    look_at: Point3,
+    up: Vec3,
+    v_fov: f64,
+    aspect_ratio: f64,
+    aperture: f64,
+    blades: Option<u32>,
+    focus_dist: Option<f64>,
+    time0: f64,
+    time1: f64,
+}
+
+impl CameraBuilder {
+    pub fn new(look_from: Point3, look_at: Point3) -> CameraBuilder {
+        CameraBuilder {
+            look_from,
+            look_at,
+            up: Vec3::new(0.0, 1.0, 0.0),
+            v_fov: 90.0,
+            aspect_ratio: 16.0 / 9.0,
+            aperture: 0.0,
+            blades: None,
+            focus_dist: None,
+            time0: 0.0,
+            time1: 0.0,
+        }
+    }
+
+    pub fn up(mut self, up: Vec3) -> CameraBuilder {
+        self.up = up;
+        self
+    }
+
+    // Degrees
+    pub fn v_fov(mut self, v_fov: f64) -> CameraBuilder {
+        self.v_fov = v_fov;
+        self
+    }
+
+    pub fn aspect_ratio(mut self, aspect_ratio: f64) -> CameraBuilder {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    // Aspect ratio of a width x height image
+    pub fn image_size(self, width: u32, height: u32) -> CameraBuilder {
+        self.aspect_ratio(width as f64 / height as f64)
+    }
+
+    pub fn aperture(mut self, aperture: f64) -> CameraBuilder {
+        self.aperture = aperture;
+        self
+    }
+
+    // Polygonal aperture, see Camera::set_blades
+    pub fn blades(mut self, blades: u32) -> CameraBuilder {
+        self.blades = Some(blades);
+        self
+    }
+
+    pub fn focus_dist(mut self, focus_dist: f64) -> CameraBuilder {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    pub fn shutter(mut self, time0: f64, time1: f64) -> CameraBuilder {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    pub fn build(&self) -> Camera {
+        let mut camera = Camera::new(
+            &self.look_from,
+            &self.look_at,
+            &self.up,
+            self.v_fov,
+            self.aspect_ratio,
+            self.aperture,
+            self.focus_dist
+                .unwrap_or_else(|| (self.look_from - self.look_at).length()),
+        )
+        .with_shutter(self.time0, self.time1);
+        camera.set_blades(self.blades);
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn orthographic_rays_are_parallel_with_offset_origins() {
+        let camera = Camera::new_orthographic(
+            &Point3::new(0.0, 0.0, 0.0),
+            &Point3::new(0.0, 0.0, -1.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            2.0,
+            2.0,
+        );
+        let mut rng = SmallRng::seed_from_u64(0);
+        let a = camera.get_ray_rng(0.25, 0.5, &mut rng);
+        let b = camera.get_ray_rng(0.75, 0.9, &mut rng);
+
+        assert_eq!(a.direction(), b.direction());
+        assert_eq!(a.direction(), Vec3::new(0.0, 0.0, -1.0));
+        assert!(((b.origin() - a.origin()) - Vec3::new(2.0, 0.8, 0.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn only_an_open_aperture_jitters_rays() {
+        let mut camera = Camera::new(
+            &Point3::new(0.0, 0.0, 0.0),
+            &Point3::new(0.0, 0.0, -1.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.0,
+            1.0,
+        );
+        let mut rng = SmallRng::seed_from_u64(0);
+        let pinhole = camera.get_ray_rng(0.3, 0.6, &mut rng);
+        assert_eq!(camera.get_ray_rng(0.3, 0.6, &mut rng), pinhole);
+
+        camera.set_aperture(0.5);
+        let first = camera.get_ray_rng(0.3, 0.6, &mut rng);
+        assert_ne!(camera.get_ray_rng(0.3, 0.6, &mut rng), first);
+    }
+
+    #[test]
+    fn builder_defaults_match_camera_new() {
+        let look_from = Point3::new(-2.0, 2.0, 1.0);
+        let look_at = Point3::new(0.0, 0.0, -1.0);
+        let built = CameraBuilder::new(look_from, look_at)
+            .v_fov(20.0)
+            .aspect_ratio(1.5)
+            .build();
+        let new = Camera::new(
+            &look_from,
+            &look_at,
+            &Vec3::new(0.0, 1.0, 0.0),
+            20.0,
+            1.5,
+            0.0,
+            (look_from - look_at).length(),
+        );
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for (s, t) in [(0.0, 0.0), (0.5, 0.5), (1.0, 0.25)] {
+            assert_eq!(
+                built.get_ray_rng(s, t, &mut rng),
+                new.get_ray_rng(s, t, &mut rng)
+            );
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_lens_sample() {
+        let camera = CameraBuilder::new(Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -1.0))
+            .aperture(0.5)
+            .build();
+        let mut a = SmallRng::seed_from_u64(3);
+        let mut b = SmallRng::seed_from_u64(3);
+        for (s, t) in [(0.1, 0.9), (0.5, 0.5), (0.7, 0.2)] {
+            assert_eq!(
+                camera.get_ray_rng(s, t, &mut a),
+                camera.get_ray_rng(s, t, &mut b)
+            );
+        }
+    }
 }
@@ -0,0 +1,118 @@
+use crate::hdr::HdrImage;
+use crate::la::Color;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Where and how often the renderer saves its progress
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointConfig {
+    pub path: PathBuf,
+    pub interval: Duration,
+}
+
+// Per-pixel sums of the first `samples` samples of a render, so it can be
+// saved and continued later
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accumulation {
+    width: u32,
+    height: u32,
+    samples: u32,
+    sums: Vec<[f64; 3]>,
+}
+
+const MAGIC: &[u8; 4] = b"RTCK";
+
+impl Accumulation {
+    pub fn new(width: u32, height: u32) -> Accumulation {
+        Accumulation {
+            width,
+            height,
+            samples: 0,
+            sums: vec![[0.0; 3]; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Samples taken in every pixel so far
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn sums_mut(&mut self) -> &mut [[f64; 3]] {
+        &mut self.sums
+    }
+
+    pub fn add_samples(&mut self, samples: u32) {
+        self.samples += samples;
+    }
+
+    // Averages the sums, black if nothing has been sampled yet
+    pub fn to_hdr_image(&self) -> HdrImage {
+        let mut image = HdrImage::new(self.width, self.height);
+        let samples = self.samples.max(1) as f64;
+        for (pixel, sum) in image.pixels_mut().iter_mut().zip(&self.sums) {
+            *pixel = (Color::from(*sum) / samples).to_f32_array();
+        }
+        image
+    }
+
+    // "RTCK", then width, height and sample count as little-endian u32s,
+    // then the sums as little-endian f64s, row by row from the top-left
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        // Written next to the target and renamed over it, so a crash while
+        // saving leaves the previous checkpoint intact
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+
+        let mut out = BufWriter::new(File::create(&partial)?);
+        out.write_all(MAGIC)?;
+        for value in [self.width, self.height, self.samples] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for value in self.sums.iter().flatten() {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.into_inner()?.sync_all()?;
+
+        std::fs::rename(partial, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Accumulation> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a render checkpoint",
+            ));
+        }
+
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut bytes = [0; 4];
+            input.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+        let (width, height, samples) = (read_u32()?, read_u32()?, read_u32()?);
+
+        let mut accumulation = Accumulation::new(width, height);
+        accumulation.samples = samples;
+        for value in accumulation.sums.iter_mut().flatten() {
+            let mut bytes = [0; 8];
+            input.read_exact(&mut bytes)?;
+            *value = f64::from_le_bytes(bytes);
+        }
+
+        Ok(accumulation)
+    }
+}
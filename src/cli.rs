@@ -0,0 +1,218 @@
+use crate::renderer::RenderSettings;
+use clap::{value_parser, Arg, Command};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+// What the command line asks for. Anything not given is taken from the
+// defaults passed to parse_args.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub settings: RenderSettings,
+    pub output: PathBuf,
+    // None leaves rayon's default (honours RAYON_NUM_THREADS)
+    pub threads: Option<usize>,
+    // Render at 1/N of the size and upscale the result, for quick looks
+    pub preview_scale: u32,
+}
+
+// Numeric options take negative numbers as values rather than flags, so
+// e.g. `--samples -3` is reported as an invalid sample count. Images need
+// at least two pixels each way, camera rays are spread over width - 1 and
+// height - 1 steps.
+fn command() -> Command {
+    Command::new("ray_tracing")
+        .about("Renders the built-in scene")
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .allow_negative_numbers(true)
+                .value_name("PIXELS")
+                .value_parser(value_parser!(u32).range(2..))
+                .help("Image width, at least 2"),
+        )
+        .arg(
+            Arg::new("height")
+                .long("height")
+                .allow_negative_numbers(true)
+                .value_name("PIXELS")
+                .value_parser(value_parser!(u32).range(2..))
+                .conflicts_with("aspect")
+                .help("Image height, at least 2"),
+        )
+        .arg(
+            Arg::new("aspect")
+                .long("aspect")
+                .allow_negative_numbers(true)
+                .value_name("RATIO")
+                .value_parser(parse_aspect)
+                .help("Width / height, sets the height from the width"),
+        )
+        .arg(
+            Arg::new("samples")
+                .long("samples")
+                .allow_negative_numbers(true)
+                .value_name("N")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Maximum samples per pixel"),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .allow_negative_numbers(true)
+                .value_name("N")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Maximum bounces per path"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("N")
+                .value_parser(value_parser!(u64))
+                .help("Render seed"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("PATH")
+                .value_parser(value_parser!(PathBuf))
+                .help("Output image, .ppm or any format the image crate encodes"),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .allow_negative_numbers(true)
+                .value_name("N")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Worker threads [default: all cores]"),
+        )
+        .arg(
+            Arg::new("preview")
+                .long("preview")
+                .allow_negative_numbers(true)
+                .value_name("SCALE")
+                .value_parser(value_parser!(u32).range(1..))
+                .help("Render at 1/SCALE of the size and upscale"),
+        )
+}
+
+fn parse_aspect(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(aspect) if aspect.is_finite() && aspect > 0.0 => Ok(aspect),
+        Ok(_) => Err("aspect ratio must be a positive number".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+// `args` includes the program name, as from std::env::args_os()
+pub fn parse_args<I, T>(
+    args: I,
+    defaults: &RenderSettings,
+    default_output: &str,
+) -> Result<Options, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = command().try_get_matches_from(args)?;
+
+    let mut settings = defaults.clone();
+    if let Some(&width) = matches.get_one::<u32>("width") {
+        settings.image_width = width;
+    }
+    if let Some(&height) = matches.get_one::<u32>("height") {
+        settings.image_height = height;
+    }
+    if let Some(&aspect) = matches.get_one::<f64>("aspect") {
+        settings.image_height = ((settings.image_width as f64 / aspect).round() as u32).max(2);
+    }
+    if let Some(&samples) = matches.get_one::<u32>("samples") {
+        settings.samples_per_pixel = samples;
+    }
+    if let Some(&max_depth) = matches.get_one::<u32>("max-depth") {
+        settings.max_depth = max_depth;
+    }
+    if let Some(&seed) = matches.get_one::<u64>("seed") {
+        settings.seed = seed;
+    }
+
+    Ok(Options {
+        settings,
+        output: matches
+            .get_one::<PathBuf>("output")
+            .cloned()
+            .unwrap_or_else(|| PathBuf::from(default_output)),
+        threads: matches.get_one::<u64>("threads").map(|&n| n as usize),
+        preview_scale: matches.get_one::<u32>("preview").copied().unwrap_or(1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argv_fills_in_the_settings() {
+        let defaults = RenderSettings::default();
+        let options = parse_args(
+            [
+                "ray_tracing",
+                "--width",
+                "320",
+                "--aspect",
+                "2",
+                "--samples",
+                "16",
+                "--max-depth",
+                "5",
+                "--seed",
+                "42",
+                "-o",
+                "out.ppm",
+                "--threads",
+                "3",
+            ],
+            &defaults,
+            "render.png",
+        )
+        .unwrap();
+
+        let expected = RenderSettings {
+            image_width: 320,
+            image_height: 160,
+            samples_per_pixel: 16,
+            max_depth: 5,
+            seed: 42,
+            ..defaults.clone()
+        };
+        assert_eq!(
+            options,
+            Options {
+                settings: expected,
+                output: PathBuf::from("out.ppm"),
+                threads: Some(3),
+                preview_scale: 1,
+            }
+        );
+
+        let options = parse_args(["ray_tracing"], &defaults, "render.png").unwrap();
+        assert_eq!(options.settings, defaults);
+        assert_eq!(options.output, PathBuf::from("render.png"));
+    }
+
+    #[test]
+    fn sizes_below_two_and_negative_samples_are_rejected() {
+        let defaults = RenderSettings::default();
+        for args in [
+            ["ray_tracing", "--width", "1"],
+            ["ray_tracing", "--height", "0"],
+            ["ray_tracing", "--samples", "-3"],
+        ] {
+            assert!(
+                parse_args(args, &defaults, "render.png").is_err(),
+                "{:?}",
+                args
+            );
+        }
+    }
+}
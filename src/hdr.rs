@@ -0,0 +1,68 @@
+use crate::la::{Color, ColorSpace, ToneMap};
+use image::{Rgb, RgbImage};
+
+// Linear, unclamped pixel colors, stored row by row from the top-left
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32) -> HdrImage {
+        HdrImage {
+            width,
+            height,
+            pixels: vec![[0.0; 3]; width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 3]] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [[f32; 3]] {
+        &mut self.pixels
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        let [r, g, b] = self.pixels[self.index(x, y)];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: Color) {
+        let index = self.index(x, y);
+        self.pixels[index] = color.to_f32_array();
+    }
+
+    // Every pixel scaled by `exposure`, a linear multiplier
+    pub fn exposed(&self, exposure: f64) -> HdrImage {
+        let mut exposed = self.clone();
+        for pixel in exposed.pixels_mut() {
+            *pixel = pixel.map(|c| (exposure * c as f64) as f32);
+        }
+        exposed
+    }
+
+    // Tone maps and encodes every pixel down to 8 bits
+    pub fn to_rgb_image(&self, tone_map: ToneMap, color_space: ColorSpace) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            Rgb(self
+                .get_pixel(x, y)
+                .as_u8_color_mapped(1, tone_map, color_space))
+        })
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+}
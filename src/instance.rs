@@ -0,0 +1,474 @@
+use crate::aabb::Aabb;
+use crate::la::{Point3, Ray, Vec3};
+use crate::mat::Mat4;
+use crate::objects::{HitRecord, Hittable};
+use rand::RngCore;
+use std::sync::Arc;
+
+pub struct Translate {
+    object: Arc<dyn Hittable>,
+    offset: Vec3,
+}
+
+impl Translate {
+    pub fn new(object: Arc<dyn Hittable>, offset: Vec3) -> Translate {
+        Translate { object, offset }
+    }
+}
+
+impl Hittable for Translate {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let moved_r = Ray::new_timed(r.origin() - self.offset, r.direction(), r.time());
+        let mut rec = self.object.hit(&moved_r, t_min, t_max)?;
+        rec.p += self.offset;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(Aabb::new(bbox.min + self.offset, bbox.max + self.offset))
+    }
+
+    fn can_sample(&self) -> bool {
+        self.object.can_sample()
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&(*origin - self.offset), direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(&(*origin - self.offset), rng)
+    }
+}
+
+pub struct RotateY {
+    object: Arc<dyn Hittable>,
+    sin_theta: f64,
+    cos_theta: f64,
+    bbox: Option<Aabb>,
+}
+
+impl RotateY {
+    // Angle in degrees, counter-clockwise looking down -y
+    pub fn new(object: Arc<dyn Hittable>, angle: f64) -> RotateY {
+        let theta = angle.to_radians();
+        let sin_theta = theta.sin();
+        let cos_theta = theta.cos();
+
+        // Rotate all eight corners and take the box around them
+        let bbox = object.bounding_box().map(|bbox| {
+            let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+            let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+            for i in 0..8 {
+                let corner = Point3::new(
+                    if i & 1 == 0 {
+                        bbox.min.x()
+                    } else {
+                        bbox.max.x()
+                    },
+                    if i & 2 == 0 {
+                        bbox.min.y()
+                    } else {
+                        bbox.max.y()
+                    },
+                    if i & 4 == 0 {
+                        bbox.min.z()
+                    } else {
+                        bbox.max.z()
+                    },
+                );
+                let rotated = Point3::new(
+                    cos_theta * corner.x() + sin_theta * corner.z(),
+                    corner.y(),
+                    -sin_theta * corner.x() + cos_theta * corner.z(),
+                );
+                min = min.min(&rotated);
+                max = max.max(&rotated);
+            }
+            Aabb::new(min, max)
+        });
+
+        RotateY {
+            object,
+            sin_theta,
+            cos_theta,
+            bbox,
+        }
+    }
+
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+}
+
+impl Hittable for RotateY {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rotated_r = Ray::new_timed(
+            self.to_object(&r.origin()),
+            self.to_object(&r.direction()),
+            r.time(),
+        );
+        let mut rec = self.object.hit(&rotated_r, t_min, t_max)?;
+
+        // Rotation keeps the normal's side relative to the ray, so front_face still holds
+        rec.p = self.to_world(&rec.p);
+        rec.normal = self.to_world(&rec.normal);
+        rec.tangent = rec.tangent.map(|tangent| self.to_world(&tangent));
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn can_sample(&self) -> bool {
+        self.object.can_sample()
+    }
+
+    // Rotations keep solid angles, so the density carries over as is
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object
+            .pdf_value(&self.to_object(origin), &self.to_object(direction))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.to_world(&self.object.random(&self.to_object(origin), rng))
+    }
+}
+
+// An object under an affine transform, e.g. a unit sphere scaled into an
+// ellipsoid. Rays are taken into object space, and hits brought back with
+// normals through the inverse transpose.
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Mat4,
+    inverse: Mat4,
+    bbox: Option<Aabb>,
+}
+
+impl Transform {
+    // Panics if `matrix` isn't invertible
+    pub fn new(object: Arc<dyn Hittable>, matrix: Mat4) -> Transform {
+        let inverse = matrix.inverse().expect("transform must be invertible");
+
+        // Transform all eight corners and take the box around them
+        let bbox = object.bounding_box().map(|bbox| {
+            let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+            let mut max = Point3::new(-f64::INFINITY, -f64::INFINITY, -f64::INFINITY);
+            for i in 0..8 {
+                let corner = Point3::new(
+                    if i & 1 == 0 {
+                        bbox.min.x()
+                    } else {
+                        bbox.max.x()
+                    },
+                    if i & 2 == 0 {
+                        bbox.min.y()
+                    } else {
+                        bbox.max.y()
+                    },
+                    if i & 4 == 0 {
+                        bbox.min.z()
+                    } else {
+                        bbox.max.z()
+                    },
+                );
+                let transformed = matrix.transform_point(&corner);
+                min = min.min(&transformed);
+                max = max.max(&transformed);
+            }
+            Aabb::new(min, max)
+        });
+
+        Transform {
+            object,
+            matrix,
+            inverse,
+            bbox,
+        }
+    }
+
+    pub fn scale(object: Arc<dyn Hittable>, scale: Vec3) -> Transform {
+        Transform::new(object, Mat4::scale(scale))
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // The map is affine, so t means the same point in both spaces
+        let object_r = Ray::new_timed(
+            self.inverse.transform_point(&r.origin()),
+            self.inverse.transform_vector(&r.direction()),
+            r.time(),
+        );
+        let mut rec = self.object.hit(&object_r, t_min, t_max)?;
+
+        // n . d is unchanged by the inverse transpose, so front_face still holds
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = self.matrix.transform_normal(&rec.normal).as_unit_vector();
+        rec.tangent = rec
+            .tangent
+            .map(|tangent| self.matrix.transform_vector(&tangent).as_unit_vector());
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bbox
+    }
+
+    fn can_sample(&self) -> bool {
+        self.object.can_sample()
+    }
+
+    // Taking unit directions into object space stretches solid angles by
+    // |det| / |d|^3 of the inverse, so the density is scaled by the same
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let object_direction = self.inverse.transform_vector(&direction.as_unit_vector());
+        let object_pdf = self
+            .object
+            .pdf_value(&self.inverse.transform_point(origin), &object_direction);
+        object_pdf * self.inverse.determinant().abs() / object_direction.length().powi(3)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let object_origin = self.inverse.transform_point(origin);
+        self.matrix
+            .transform_vector(&self.object.random(&object_origin, rng))
+    }
+}
+
+// Swaps the inside and outside of an object, e.g. for meshes with inverted
+// winding or a one-sided light facing the wrong way. Only front_face
+// changes: the hit normal already points back against the ray either way.
+pub struct FlipFace {
+    object: Arc<dyn Hittable>,
+}
+
+impl FlipFace {
+    pub fn new(object: Arc<dyn Hittable>) -> FlipFace {
+        FlipFace { object }
+    }
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut rec = self.object.hit(r, t_min, t_max)?;
+        rec.front_face = !rec.front_face;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn can_sample(&self) -> bool {
+        self.object.can_sample()
+    }
+
+    // Still the same surface to aim light samples at
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(origin, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BvhNode;
+    use crate::la::Color;
+    use crate::objects::{BoxObj, Lambertian, Material, Sphere, Triangle, XyRect, XzRect};
+    use crate::stats::StatsAccumulator;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+    use std::f64::consts::PI;
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, gray()))
+    }
+
+    #[test]
+    fn translate_moves_the_hit_point() {
+        let r = Ray::new(Point3::new(2.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(unit_sphere().hit(&r, 0.001, f64::INFINITY).is_none());
+
+        let moved = Translate::new(unit_sphere(), Vec3::new(2.0, 0.0, 0.0));
+        let rec = moved.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(2.0, 0.0, 1.0)).length() < 1e-9);
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn rotating_a_box_90_degrees_swaps_x_and_z_extents() {
+        let b = Arc::new(BoxObj::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(3.0, 1.0, 1.0),
+            gray(),
+        ));
+        let rotated = RotateY::new(b, 90.0);
+        let bbox = rotated.bounding_box().unwrap();
+        let extent = bbox.max - bbox.min;
+        assert!((extent - Vec3::new(1.0, 1.0, 3.0)).length() < 1e-9);
+
+        // The long side now runs along -z instead of +x
+        let r = Ray::new(Point3::new(2.5, 0.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(rotated.hit(&r, 0.001, f64::INFINITY).is_none());
+        let r = Ray::new(Point3::new(0.5, 0.5, -2.5), Vec3::new(0.0, 1.0, 0.0));
+        let rec = rotated.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.p - Point3::new(0.5, 1.0, -2.5)).length() < 1e-9);
+    }
+
+    // The solid angle `object` covers from `origin`, estimated once from its
+    // own samples as the mean of 1 / pdf and once by counting the directions
+    // of a Fibonacci lattice, spread evenly over the sphere, that hit it.
+    // They only agree if random() and pdf_value() describe the same
+    // distribution.
+    fn solid_angles(object: &dyn Hittable, origin: &Point3) -> (f64, f64) {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let samples = 100_000;
+
+        let sampled = (0..samples)
+            .map(|_| 1.0 / object.pdf_value(origin, &object.random(origin, &mut rng)))
+            .sum::<f64>()
+            / samples as f64;
+
+        let golden_angle = PI * (3.0 - 5f64.sqrt());
+        let hits = (0..samples)
+            .filter(|&i| {
+                let z = 1.0 - (2 * i + 1) as f64 / samples as f64;
+                let (sin, cos) = (i as f64 * golden_angle).sin_cos();
+                let radius = (1.0 - z * z).sqrt();
+                let r = Ray::new(*origin, Vec3::new(radius * cos, radius * sin, z));
+                object.hit(&r, 0.001, f64::INFINITY).is_some()
+            })
+            .count();
+        let counted = 4.0 * PI * hits as f64 / samples as f64;
+        (sampled, counted)
+    }
+
+    #[test]
+    fn wrapped_lights_sample_the_directions_they_cover() {
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let rect = Arc::new(XyRect::new(-0.5, 0.5, -0.5, 0.5, -1.0, gray()));
+        let mut triangles: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Triangle::new(
+                Point3::new(-1.0, -1.0, -1.0),
+                Point3::new(0.0, -1.0, -1.0),
+                Point3::new(-1.0, 0.0, -1.0),
+                gray(),
+            )),
+            Arc::new(Triangle::new(
+                Point3::new(0.0, 0.0, -1.5),
+                Point3::new(1.0, 0.0, -1.5),
+                Point3::new(0.0, 1.0, -1.5),
+                gray(),
+            )),
+        ];
+        let lights: [(&str, Arc<dyn Hittable>); 4] = [
+            (
+                "translate",
+                Arc::new(Translate::new(unit_sphere(), Vec3::new(0.0, 0.0, -3.0))),
+            ),
+            ("rotate_y", Arc::new(RotateY::new(rect, 30.0))),
+            (
+                "transform",
+                Arc::new(Transform::new(
+                    unit_sphere(),
+                    Mat4::translate(Vec3::new(0.0, 0.0, -3.0))
+                        * Mat4::scale(Vec3::new(1.5, 0.5, 0.5)),
+                )),
+            ),
+            ("bvh", Arc::new(BvhNode::new(&mut triangles))),
+        ];
+
+        for (name, light) in lights {
+            assert!(light.can_sample(), "{}", name);
+            let (sampled, counted) = solid_angles(light.as_ref(), &origin);
+            assert!(
+                (sampled - counted).abs() < 0.02 * counted,
+                "{}: {} vs {}",
+                name,
+                sampled,
+                counted
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_primitives_behind_a_wrapper_count_as_hit_calls() {
+        let unit_box = BoxObj::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            gray(),
+        );
+        let moved = Translate::new(Arc::new(unit_box), Vec3::new(0.0, 0.0, -3.0));
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let stats = StatsAccumulator::default();
+        assert!(stats
+            .count(|| moved.hit(&r, 0.001, f64::INFINITY))
+            .is_some());
+        // The box's six rects, and nothing for the box or the Translate
+        let expected = if cfg!(feature = "stats") { 6 } else { 0 };
+        assert_eq!(stats.total().hit_calls, expected);
+    }
+
+    #[test]
+    fn sphere_scaled_along_x_is_an_ellipsoid() {
+        let ellipsoid = Transform::scale(unit_sphere(), Vec3::new(2.0, 1.0, 1.0));
+        for side in [1.0, -1.0] {
+            let r = Ray::new(
+                Point3::new(5.0 * side, 0.0, 0.0),
+                Vec3::new(-side, 0.0, 0.0),
+            );
+            let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!(rec.p.approx_eq(&Point3::new(2.0 * side, 0.0, 0.0), 1e-9));
+            assert!(rec.normal.approx_eq(&Vec3::new(side, 0.0, 0.0), 1e-9));
+        }
+
+        // Off the axes the normal follows the inverse transpose, (x / 4, y, z)
+        let x = f64::sqrt(2.0);
+        let r = Ray::new(Point3::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = ellipsoid.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let y = f64::sqrt(0.5);
+        assert!(rec.p.approx_eq(&Point3::new(x, y, 0.0), 1e-9));
+        let normal = Vec3::new(x / 4.0, y, 0.0).as_unit_vector();
+        assert!(rec.normal.approx_eq(&normal, 1e-9));
+    }
+
+    #[test]
+    fn flip_face_reports_the_other_side() {
+        let rect = || Arc::new(XzRect::new(-1.0, 1.0, -1.0, 1.0, 1.0, gray()));
+        let flipped = FlipFace::new(rect());
+        // From below, against the rect's +y normal
+        let up = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+
+        let plain = rect().hit(&up, 0.001, f64::INFINITY).unwrap();
+        let rec = flipped.hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert!(!plain.front_face);
+        assert!(rec.front_face);
+        // Same hit, still shaded facing the ray
+        assert_eq!(rec.t, plain.t);
+        assert_eq!(rec.normal, plain.normal);
+    }
+}
@@ -1,6 +1,10 @@
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 
-#[derive(Debug, Copy, Clone)]
+// Serializes as a plain [x, y, z] array
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Vec3 {
     e: [f64; 3],
 }
@@ -45,7 +49,7 @@ impl Vec3 {
         )
     }
 
-    pub fn normalize(&mut self) -> () {
+    pub fn normalize(&mut self) {
         *self /= self.length();
     }
 
@@ -55,48 +59,195 @@ impl Vec3 {
 
     pub fn is_near_zero(&self) -> bool {
         let s = 1e-8;
-        return ((self.e[0].abs() < s) && (self.e[1].abs() < s) && (self.e[2].abs() < s));
+        (self.e[0].abs() < s) && (self.e[1].abs() < s) && (self.e[2].abs() < s)
+    }
+
+    // Every component within eps of the other's
+    pub fn approx_eq(&self, v: &Vec3, eps: f64) -> bool {
+        (0..3).all(|i| (self.e[i] - v.e[i]).abs() <= eps)
+    }
+
+    // self at t = 0, v at t = 1
+    pub fn lerp(&self, v: &Vec3, t: f64) -> Vec3 {
+        (1.0 - t) * *self + t * *v
+    }
+
+    pub fn clamp(&self, min: f64, max: f64) -> Vec3 {
+        self.map(|c| c.clamp(min, max))
+    }
+
+    pub fn min(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.e[0].min(v.e[0]),
+            self.e[1].min(v.e[1]),
+            self.e[2].min(v.e[2]),
+        )
+    }
+
+    pub fn max(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.e[0].max(v.e[0]),
+            self.e[1].max(v.e[1]),
+            self.e[2].max(v.e[2]),
+        )
+    }
+
+    pub fn component_max(&self) -> f64 {
+        self.e[0].max(self.e[1]).max(self.e[2])
+    }
+
+    pub fn component_min(&self) -> f64 {
+        self.e[0].min(self.e[1]).min(self.e[2])
+    }
+
+    pub fn component_sum(&self) -> f64 {
+        self.e[0] + self.e[1] + self.e[2]
+    }
+
+    // Rec. 709 weights for a linear RGB color
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.e[0] + 0.7152 * self.e[1] + 0.0722 * self.e[2]
+    }
+
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Vec3 {
+        Vec3::new(f(self.e[0]), f(self.e[1]), f(self.e[2]))
+    }
+
+    // Unit vector for polar angle theta, measured from +y, and azimuth phi,
+    // measured around y from +x towards +z
+    pub fn from_spherical(theta: f64, phi: f64) -> Vec3 {
+        Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        )
+    }
+
+    // (theta, phi) as in from_spherical, with theta in [0, pi] and phi in
+    // (-pi, pi]. Doesn't need to be a unit vector.
+    pub fn to_spherical(self) -> (f64, f64) {
+        let unit = self.as_unit_vector();
+        let theta = unit.y().clamp(-1.0, 1.0).acos();
+        let phi = f64::atan2(unit.z(), unit.x());
+        (theta, phi)
+    }
+
+    // 0xRRGGBB as written in color pickers and CSS. The components are sRGB
+    // encoded, so they're decoded to the linear values rendering works in.
+    pub fn from_hex(hex: u32) -> Color {
+        let channel = |shift: u32| srgb_to_linear(((hex >> shift) & 0xFF) as f64 / 255.0);
+        Color::new(channel(16), channel(8), channel(0))
+    }
+
+    // Hue in degrees (0 red, 120 green, 240 blue), saturation and value in
+    // [0, 1]. The result is used as is, without sRGB decoding.
+    pub fn from_hsv(h: f64, s: f64, v: f64) -> Color {
+        let h = h.rem_euclid(360.0) / 60.0;
+        let c = v * s;
+        let x = c * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        Color::new(r + m, g + m, b + m)
+    }
+
+    // Narrowed to f32 for image buffers and GPU uploads
+    pub fn to_f32_array(self) -> [f32; 3] {
+        [self.e[0] as f32, self.e[1] as f32, self.e[2] as f32]
+    }
+
+    // Linear color as an f32 pixel for the image crate
+    pub fn as_rgb_f32(&self) -> image::Rgb<f32> {
+        image::Rgb(self.to_f32_array())
     }
 
     pub fn as_u8_color(&self, samples: u32) -> [u8; 3] {
-        let r = (self.e[0] / samples as f64).clamp(0.0, 1.0);
-        let g = (self.e[1] / samples as f64).clamp(0.0, 1.0);
-        let b = (self.e[2] / samples as f64).clamp(0.0, 1.0);
+        self.as_u8_color_mapped(samples, ToneMap::Clamp, ColorSpace::Gamma(2.0))
+    }
+
+    // Averages over `samples`, tone maps, then encodes for display
+    pub fn as_u8_color_mapped(
+        &self,
+        samples: u32,
+        tone_map: ToneMap,
+        color_space: ColorSpace,
+    ) -> [u8; 3] {
+        let c = (*self / samples as f64)
+            .map(|c| tone_map.apply(c))
+            .clamp(0.0, 1.0)
+            .map(|c| color_space.encode(c));
 
         [
-            (r.sqrt() * u8::MAX as f64) as u8,
-            (g.sqrt() * u8::MAX as f64) as u8,
-            (b.sqrt() * u8::MAX as f64) as u8,
+            (c.e[0] * u8::MAX as f64) as u8,
+            (c.e[1] * u8::MAX as f64) as u8,
+            (c.e[2] * u8::MAX as f64) as u8,
         ]
     }
 
-    pub fn rand_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn rand_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         loop {
             let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-            if (p.length_squared() >= 1.0) {
+            if p.length_squared() >= 1.0 {
                 continue;
             }
             return p;
         }
     }
-    
-    pub fn rand_in_unit_sphere() -> Vec3 {
+
+    // Uniform in the regular polygon inscribed in the unit circle (z = 0),
+    // with a corner on +x. Fewer than 3 blades is taken as 3.
+    pub fn rand_in_unit_polygon<R: Rng + ?Sized>(blades: u32, rng: &mut R) -> Vec3 {
+        let blades = blades.max(3);
+
+        // The polygon is `blades` equal triangles around the center: pick one,
+        // then a uniform point in it, folding the far half of the unit square
+        // back onto the triangle
+        let sector = 2.0 * PI / blades as f64;
+        let angle = rng.gen_range(0..blades) as f64 * sector;
+        let a = Vec3::new(angle.cos(), angle.sin(), 0.0);
+        let b = Vec3::new((angle + sector).cos(), (angle + sector).sin(), 0.0);
+
+        let (mut s, mut t) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if s + t > 1.0 {
+            s = 1.0 - s;
+            t = 1.0 - t;
+        }
+        s * a + t * b
+    }
+
+    pub fn rand_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
         loop {
-            let p = Vec3::rand(-1.0, 1.0);
-            if (p.length_squared() >= 1.0) {
+            let p = Vec3::rand(rng, -1.0, 1.0);
+            if p.length_squared() >= 1.0 {
                 continue;
             }
             return p;
         }
     }
-    
-    pub fn rand_unit_vector() -> Vec3 {
-        Vec3::rand_in_unit_sphere().as_unit_vector()
+
+    pub fn rand_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
+        Vec3::rand_in_unit_sphere(rng).as_unit_vector()
+    }
+
+    // Cosine-weighted over the hemisphere around +z
+    pub fn rand_cosine_direction<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
+        let r1: f64 = rng.gen();
+        let r2: f64 = rng.gen();
+        let phi = 2.0 * PI * r1;
+        Vec3::new(
+            phi.cos() * r2.sqrt(),
+            phi.sin() * r2.sqrt(),
+            (1.0 - r2).sqrt(),
+        )
     }
 
-    pub fn rand(min: f64, max: f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
+    pub fn rand<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> Vec3 {
         Vec3::new(
             rng.gen_range(min..max),
             rng.gen_range(min..max),
@@ -104,8 +255,16 @@ impl Vec3 {
         )
     }
 
+    // Unit vector halfway between two directions, e.g. towards the viewer and
+    // the light. It's the normal that would mirror one into the other.
+    pub fn half_vector(a: &Vec3, b: &Vec3) -> Vec3 {
+        (a.as_unit_vector() + b.as_unit_vector()).as_unit_vector()
+    }
+
+    // Mirrors the vector about the plane with unit normal `normal`, which can
+    // be a microfacet's as well as the surface's
     pub fn reflect(&self, normal: &Vec3) -> Vec3 {
-        return *self - 2.0 * Vec3::dot(self, normal) * (*normal);
+        *self - 2.0 * Vec3::dot(self, normal) * (*normal)
     }
 
     pub fn refract(&self, normal: &Vec3, refraction_ratio: f64) -> Vec3 {
@@ -113,7 +272,15 @@ impl Vec3 {
 
         let r_out_perp = refraction_ratio * (*self + cos_theta * (*normal));
         let r_out_parallel = -f64::sqrt((1.0 - r_out_perp.length_squared()).abs()) * (*normal);
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
+    }
+
+    // Rodrigues' rotation by `angle` degrees, counter-clockwise looking down
+    // `axis` towards the origin. The axis doesn't need to be a unit vector.
+    pub fn rotate_around(&self, axis: &Vec3, angle: f64) -> Vec3 {
+        let k = axis.as_unit_vector();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        *self * cos + Vec3::cross(&k, self) * sin + k * Vec3::dot(&k, self) * (1.0 - cos)
     }
 }
 
@@ -146,7 +313,7 @@ impl std::ops::Add<Vec3> for f64 {
 }
 
 impl std::ops::AddAssign for Vec3 {
-    fn add_assign(&mut self, rhs: Vec3) -> () {
+    fn add_assign(&mut self, rhs: Vec3) {
         self.e[0] += rhs.e[0];
         self.e[1] += rhs.e[1];
         self.e[2] += rhs.e[2];
@@ -166,7 +333,7 @@ impl std::ops::Sub for Vec3 {
 }
 
 impl std::ops::SubAssign for Vec3 {
-    fn sub_assign(&mut self, rhs: Vec3) -> () {
+    fn sub_assign(&mut self, rhs: Vec3) {
         self.e[0] -= rhs.e[0];
         self.e[1] -= rhs.e[1];
         self.e[2] -= rhs.e[2];
@@ -202,7 +369,7 @@ impl std::ops::Mul<Vec3> for f64 {
 }
 
 impl std::ops::MulAssign for Vec3 {
-    fn mul_assign(&mut self, rhs: Vec3) -> () {
+    fn mul_assign(&mut self, rhs: Vec3) {
         self.e[0] *= rhs.e[0];
         self.e[1] *= rhs.e[1];
         self.e[2] *= rhs.e[2];
@@ -230,7 +397,7 @@ impl std::ops::Div<f64> for Vec3 {
 }
 
 impl std::ops::DivAssign for Vec3 {
-    fn div_assign(&mut self, rhs: Vec3) -> () {
+    fn div_assign(&mut self, rhs: Vec3) {
         self.e[0] /= rhs.e[0];
         self.e[1] /= rhs.e[1];
         self.e[2] /= rhs.e[2];
@@ -238,7 +405,7 @@ impl std::ops::DivAssign for Vec3 {
 }
 
 impl std::ops::DivAssign<f64> for Vec3 {
-    fn div_assign(&mut self, rhs: f64) -> () {
+    fn div_assign(&mut self, rhs: f64) {
         self.e[0] /= rhs;
         self.e[1] /= rhs;
         self.e[2] /= rhs;
@@ -266,29 +433,533 @@ impl std::ops::IndexMut<usize> for Vec3 {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl From<[f64; 3]> for Vec3 {
+    fn from(e: [f64; 3]) -> Vec3 {
+        Vec3 { e }
+    }
+}
+
+impl From<Vec3> for [f64; 3] {
+    fn from(v: Vec3) -> [f64; 3] {
+        v.e
+    }
+}
+
+// Formats as "x,y,z", the same form FromStr reads
+impl std::fmt::Display for Vec3 {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{},{},{}", self.e[0], self.e[1], self.e[2])
+    }
+}
+
+// Parses three comma-separated numbers, e.g. "1.0,-2,0.5". Whitespace around
+// the components is ignored.
+impl std::str::FromStr for Vec3 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Vec3, String> {
+        let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+        if parts.len() != 3 {
+            return Err(format!(
+                "expected three comma-separated components, found {} in \"{}\"",
+                parts.len(),
+                s
+            ));
+        }
+
+        let mut e = [0.0; 3];
+        for (component, part) in e.iter_mut().zip(parts) {
+            *component = part
+                .parse()
+                .map_err(|err| format!("invalid component \"{}\" in \"{}\": {}", part, s, err))?;
+        }
+        Ok(Vec3 { e })
+    }
+}
+
+// The sRGB transfer function's inverse, for one channel in [0, 1]
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// The sRGB transfer function, for one channel in [0, 1]
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Orthonormal basis for turning local directions, where w is +z, into world space
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    pub fn from_w(w: &Vec3) -> Onb {
+        let w = w.as_unit_vector();
+        // Any vector not parallel to w will do
+        let a = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v = Vec3::cross(&w, &a).as_unit_vector();
+        let u = Vec3::cross(&v, &w);
+        Onb { u, v, w }
+    }
+
+    // u along `tangent`, made perpendicular to w. Falls back to from_w()
+    // without one, or with one parallel to w.
+    pub fn from_w_and_tangent(w: &Vec3, tangent: Option<Vec3>) -> Onb {
+        let w = w.as_unit_vector();
+        match tangent
+            .map(|t| t - w * Vec3::dot(&t, &w))
+            .filter(|t| !t.is_near_zero())
+        {
+            Some(u) => {
+                let u = u.as_unit_vector();
+                Onb {
+                    u,
+                    v: Vec3::cross(&w, &u),
+                    w,
+                }
+            }
+            None => Onb::from_w(&w),
+        }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        a.x() * self.u + a.y() * self.v + a.z() * self.w
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Ray {
+    #[serde(rename = "origin")]
     orig: Point3,
+    #[serde(rename = "direction")]
     dir: Vec3,
+    #[serde(default)]
+    time: f64,
+    // The color (0 red, 1 green, 2 blue) a path has been narrowed to by
+    // dispersive glass, kept for the rest of the path
+    #[serde(skip)]
+    channel: Option<usize>,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Ray {
+        Ray::new_timed(origin, direction, 0.0)
+    }
+
+    pub fn new_timed(origin: Point3, direction: Vec3, time: f64) -> Ray {
         Ray {
             orig: origin,
-            dir: direction.as_unit_vector(),
+            dir: direction,
+            time,
+            channel: None,
         }
     }
 
+    pub fn with_channel(self, channel: Option<usize>) -> Ray {
+        Ray { channel, ..self }
+    }
+
     pub fn origin(&self) -> Point3 {
         self.orig
     }
 
+    // Not normalized, so t in at(t) is measured in multiples of this vector
     pub fn direction(&self) -> Vec3 {
         self.dir
     }
 
+    pub fn unit_direction(&self) -> Vec3 {
+        self.dir.as_unit_vector()
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn channel(&self) -> Option<usize> {
+        self.channel
+    }
+
     pub fn at(&self, t: f64) -> Point3 {
         self.orig + t * self.dir
     }
 }
+
+// SplitMix64 finalizer, for turning a seed into well-mixed bits
+pub fn splitmix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMap {
+    // Anything above 1.0 blows out to white
+    Clamp,
+    // c / (1 + c)
+    Reinhard,
+    // Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(&self, c: f64) -> f64 {
+        match self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (1.0 + c),
+            ToneMap::Aces => (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14),
+        }
+    }
+}
+
+// How linear values in [0, 1] are encoded for display
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    // c^(1 / gamma), 1.0 writes linear values. 2.0 is a cheap approximation
+    // of sRGB.
+    Gamma(f64),
+    // The exact piecewise sRGB curve
+    Srgb,
+}
+
+impl ColorSpace {
+    pub fn encode(&self, c: f64) -> f64 {
+        match *self {
+            ColorSpace::Gamma(1.0) => c,
+            ColorSpace::Gamma(2.0) => c.sqrt(),
+            ColorSpace::Gamma(gamma) => c.powf(1.0 / gamma),
+            ColorSpace::Srgb => linear_to_srgb(c),
+        }
+    }
+}
+
+// assert_eq! for Vec3s, passing when every component is within `eps`
+#[cfg(test)]
+macro_rules! assert_vec3_eq {
+    ($left:expr, $right:expr, $eps:expr) => {{
+        let (left, right, eps) = (&$left, &$right, $eps);
+        assert!(
+            left.approx_eq(right, eps),
+            "{:?} is not within {} of {:?}",
+            left,
+            eps,
+            right
+        );
+    }};
+}
+
+#[cfg(test)]
+pub(crate) use assert_vec3_eq;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn tone_maps_differ_above_one() {
+        let bright = Color::new(4.0, 4.0, 4.0);
+        let mapped = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Aces]
+            .map(|tone_map| bright.as_u8_color_mapped(1, tone_map, ColorSpace::Gamma(1.0))[0]);
+
+        assert_eq!(mapped[0], 255);
+        // 4 / (1 + 4)
+        assert_eq!(mapped[1], 204);
+        assert!(mapped[2] > mapped[1] && mapped[2] < 255, "{:?}", mapped);
+    }
+
+    #[test]
+    fn at_is_measured_in_multiples_of_a_non_unit_direction() {
+        let r = Ray::new(Point3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 3.0, 4.0));
+        let p = r.at(2.0);
+        assert_eq!(p, Point3::new(1.0, 6.0, 8.0));
+        assert_eq!((p - r.origin()).length(), 2.0 * r.direction().length());
+        assert!((r.unit_direction() - Vec3::new(0.0, 0.6, 0.8)).length() < 1e-12);
+    }
+
+    #[test]
+    fn clamp_min_max_and_map_work_per_component() {
+        let v = Vec3::new(-0.5, 0.25, 1.5);
+        assert_eq!(v.clamp(0.0, 1.0), Vec3::new(0.0, 0.25, 1.0));
+
+        let w = Vec3::new(0.0, -1.0, 2.0);
+        assert_eq!(v.min(&w), Vec3::new(-0.5, -1.0, 1.5));
+        assert_eq!(v.max(&w), Vec3::new(0.0, 0.25, 2.0));
+        assert_eq!(v.map(|c| 2.0 * c), Vec3::new(-1.0, 0.5, 3.0));
+    }
+
+    #[test]
+    fn gamma_one_is_linear_and_gamma_two_is_sqrt() {
+        let gray = 0.18;
+        let color = Color::new(gray, gray, gray);
+        let linear = (gray * 255.0) as u8;
+        assert_eq!(
+            color.as_u8_color_mapped(1, ToneMap::Clamp, ColorSpace::Gamma(1.0)),
+            [linear; 3]
+        );
+
+        // The old fixed sqrt encoding is still the default
+        let old = (f64::sqrt(gray) * 255.0) as u8;
+        assert_eq!(
+            color.as_u8_color_mapped(1, ToneMap::Clamp, ColorSpace::Gamma(2.0)),
+            [old; 3]
+        );
+        assert_eq!(color.as_u8_color(1), [old; 3]);
+    }
+
+    #[test]
+    fn vec3_and_ray_round_trip_through_json() {
+        let v = Vec3::new(1.5, -2.0, 0.25);
+        let json = serde_json::to_string(&v).unwrap();
+        assert_eq!(json, "[1.5,-2.0,0.25]");
+        assert_eq!(serde_json::from_str::<Vec3>(&json).unwrap(), v);
+
+        let r = Ray::new_timed(v, Vec3::new(0.0, 0.0, -1.0), 0.5);
+        let json = serde_json::to_string(&r).unwrap();
+        assert_eq!(serde_json::from_str::<Ray>(&json).unwrap(), r);
+    }
+
+    #[test]
+    fn mirror_reflects_a_45_degree_ray() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let incoming = Vec3::new(1.0, -1.0, 0.0).as_unit_vector();
+        let reflected = incoming.reflect(&normal);
+        assert!((reflected - Vec3::new(1.0, 1.0, 0.0).as_unit_vector()).length() < 1e-12);
+    }
+
+    #[test]
+    fn refraction_into_a_denser_medium_bends_toward_the_normal() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let incoming = Vec3::new(1.0, -1.0, 0.0).as_unit_vector();
+        let refracted = incoming.refract(&normal, 1.0 / 1.5);
+
+        // Snell's law, sin(theta_t) = sin(theta_i) / 1.5
+        let sin_in = std::f64::consts::FRAC_1_SQRT_2;
+        assert!((refracted.x() - sin_in / 1.5).abs() < 1e-12);
+        assert!((refracted.length() - 1.0).abs() < 1e-12);
+        assert!(refracted.y() < incoming.y());
+    }
+
+    #[test]
+    fn lerp_ends_at_its_endpoints() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(-4.0, 0.5, 8.0);
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert_eq!(a.lerp(&b, 1.0), b);
+        assert_eq!(a.lerp(&b, 0.5), Vec3::new(-1.5, 1.25, 5.5));
+    }
+
+    #[test]
+    fn cosine_directions_average_two_thirds_up() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let samples = 100_000;
+        let mut sum_z = 0.0;
+        for _ in 0..samples {
+            let d = Vec3::rand_cosine_direction(&mut rng);
+            assert!(d.z() >= 0.0);
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            sum_z += d.z();
+        }
+        assert!((sum_z / samples as f64 - 2.0 / 3.0).abs() < 0.005);
+
+        // Carried over to a normal by its basis
+        let normal = Vec3::new(1.0, -2.0, 0.5);
+        let onb = Onb::from_w(&normal);
+        for _ in 0..1000 {
+            let d = onb.local(&Vec3::rand_cosine_direction(&mut rng));
+            assert!(Vec3::dot(&d, &normal) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn spherical_round_trips_including_the_poles() {
+        let directions = [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.3, -0.5, 0.8).as_unit_vector(),
+        ];
+        for d in directions {
+            let (theta, phi) = d.to_spherical();
+            assert!((0.0..=PI).contains(&theta));
+            assert!(
+                (Vec3::from_spherical(theta, phi) - d).length() < 1e-12,
+                "{:?}",
+                d
+            );
+        }
+        assert_eq!(Vec3::new(0.0, 2.0, 0.0).to_spherical().0, 0.0);
+    }
+
+    #[test]
+    fn approx_eq_compares_each_component_within_eps() {
+        let v = Vec3::new(1.0, -2.0, -0.5);
+        assert!(v.approx_eq(&Vec3::new(1.05, -2.05, -0.45), 0.1));
+        assert!(!v.approx_eq(&Vec3::new(1.0, -2.2, -0.5), 0.1));
+        assert!(!v.approx_eq(&Vec3::new(1.0, 2.0, -0.5), 0.1));
+        assert!(!v.approx_eq(&Vec3::new(1.0, -2.0, 0.5), 0.1));
+        assert_vec3_eq!(v, Vec3::new(1.0, -2.0, -0.5 + 1e-10), 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not within")]
+    fn assert_vec3_eq_fails_outside_eps() {
+        assert_vec3_eq!(Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -0.2), 0.1);
+    }
+
+    #[test]
+    fn green_is_the_brightest_primary() {
+        let [red, green, blue] = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+        ]
+        .map(|c| c.luminance());
+        assert!(green > red && red > blue);
+        assert!((red + green + blue - 1.0).abs() < 1e-12);
+
+        let v = Vec3::new(0.2, 0.9, 0.5);
+        assert_eq!(v.component_max(), 0.9);
+        assert_eq!(v.component_min(), 0.2);
+    }
+
+    #[test]
+    fn display_and_from_str_round_trip() {
+        let v = Vec3::new(-1.5, 0.25, 3.0);
+        assert_eq!(v.to_string().parse::<Vec3>(), Ok(v));
+        assert_eq!(" -1.5, 0.25 ,3 ".parse::<Vec3>(), Ok(v));
+
+        let err = "1.0,2.0".parse::<Vec3>().unwrap_err();
+        assert!(err.contains("three"), "{}", err);
+        let err = "1.0,x,2.0".parse::<Vec3>().unwrap_err();
+        assert!(err.contains("\"x\""), "{}", err);
+    }
+
+    #[test]
+    fn four_blade_samples_fill_the_diamond() {
+        let mut rng = SmallRng::seed_from_u64(10);
+        let mut quadrants = [false; 4];
+        for _ in 0..10_000 {
+            let p = Vec3::rand_in_unit_polygon(4, &mut rng);
+            assert!(p.x().abs() + p.y().abs() <= 1.0 + 1e-12, "{:?}", p);
+            assert_eq!(p.z(), 0.0);
+            quadrants[(p.x() < 0.0) as usize * 2 + (p.y() < 0.0) as usize] = true;
+        }
+        assert_eq!(quadrants, [true; 4]);
+    }
+
+    #[test]
+    fn array_conversions_round_trip_and_f32_narrows() {
+        let v = Vec3::new(0.1, -2.5, 1e10);
+        assert_eq!(Vec3::from(<[f64; 3]>::from(v)), v);
+        assert_eq!(
+            <[f64; 3]>::from(Vec3::from([1.0, 2.0, 3.0])),
+            [1.0, 2.0, 3.0]
+        );
+
+        let narrow = v.to_f32_array();
+        assert_eq!(narrow, [0.1f32, -2.5, 1e10]);
+        assert_ne!(narrow[0] as f64, 0.1);
+        assert!((narrow[0] as f64 - 0.1).abs() < 1e-8);
+        assert_eq!(v.as_rgb_f32().0, narrow);
+    }
+
+    #[test]
+    fn hex_and_hsv_primaries() {
+        let red = Color::new(1.0, 0.0, 0.0);
+        assert_vec3_eq!(Color::from_hex(0xFF0000), red, 1e-12);
+        // Mid gray is sRGB encoded, so it decodes darker
+        assert!((Color::from_hex(0x808080).x() - 0.2158).abs() < 1e-4);
+
+        assert_vec3_eq!(Color::from_hsv(0.0, 1.0, 1.0), red, 1e-12);
+        assert_vec3_eq!(
+            Color::from_hsv(120.0, 1.0, 1.0),
+            Color::new(0.0, 1.0, 0.0),
+            1e-12
+        );
+        assert_vec3_eq!(
+            Color::from_hsv(240.0, 1.0, 1.0),
+            Color::new(0.0, 0.0, 1.0),
+            1e-12
+        );
+        assert_vec3_eq!(
+            Color::from_hsv(60.0, 0.0, 0.5),
+            Color::new(0.5, 0.5, 0.5),
+            1e-12
+        );
+    }
+
+    #[test]
+    fn srgb_encodes_mid_gray_brighter_than_gamma_two() {
+        let srgb = ColorSpace::Srgb.encode(0.18);
+        assert!((srgb - 0.4614).abs() < 1e-4, "{}", srgb);
+        assert!((ColorSpace::Gamma(2.0).encode(0.18) - srgb).abs() > 0.03);
+
+        let gray = Color::new(0.18, 0.18, 0.18);
+        assert_eq!(
+            gray.as_u8_color_mapped(1, ToneMap::Clamp, ColorSpace::Srgb),
+            [117; 3]
+        );
+        assert_eq!(gray.as_u8_color(1), [108; 3]);
+    }
+
+    #[test]
+    fn rodrigues_rotation() {
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        assert_vec3_eq!(x.rotate_around(&z, 90.0), Vec3::new(0.0, 1.0, 0.0), 1e-12);
+
+        let v = Vec3::new(1.0, -2.0, 0.5);
+        assert_vec3_eq!(v.rotate_around(&(3.0 * v), 73.0), v, 1e-12);
+
+        let axis = Vec3::new(0.3, 1.0, -0.7);
+        for angle in [10.0, 135.0, -250.0] {
+            let rotated = v.rotate_around(&axis, angle);
+            assert!((rotated.length() - v.length()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn half_vector_of_mirrored_directions_is_the_normal() {
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let incoming = Vec3::new(-1.0, 2.0, 0.5);
+        let outgoing = (-incoming).reflect(&normal);
+        let half = Vec3::half_vector(&incoming, &(3.0 * outgoing));
+        assert_vec3_eq!(half, normal, 1e-12);
+
+        let half = Vec3::half_vector(&Vec3::new(1.0, 0.2, 0.0), &Vec3::new(0.0, 4.0, -1.0));
+        assert!((half.length() - 1.0).abs() < 1e-12);
+    }
+}
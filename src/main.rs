@@ -1,11 +1,24 @@
 #![allow(unused)]
 
-use rand::Rng;
 use std::f64::consts::PI;
-use std::f64::INFINITY;
-use std::rc::Rc;
-
-use image::{ImageBuffer, Rgb, RgbImage};
+use std::sync::Arc;
+
+mod aabb;
+mod animation;
+mod bvh;
+mod checkpoint;
+mod cli;
+mod hdr;
+mod instance;
+mod mat;
+mod mesh;
+mod output;
+mod pdf;
+mod perlin;
+mod post;
+mod progress;
+mod scene;
+mod stats;
 
 mod la;
 use la::*;
@@ -16,114 +29,123 @@ use objects::*;
 mod camera;
 use camera::*;
 
-fn ray_color(r: &Ray, world: &HittableList, depth: u32) -> Color {
-    if depth == 0 {
-        return Color::new(0.0, 0.0, 0.0);
-    }
+mod texture;
+use texture::*;
 
-    match world.hit(r, 0.001, INFINITY) {
-        Some(rec) => match rec.mat.scatter(r, &rec) {
-            Some((r_scattered, attenuation)) => {
-                return attenuation * ray_color(&r_scattered, world, depth - 1);
-            }
-            None => {
-                return Color::new(0.0, 0.0, 0.0);
-            }
-        },
-        None => {
-            let t = 0.5 * (r.direction().y() + 1.0);
-            return (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0);
-        }
-    }
-}
+mod background;
+use background::*;
+
+mod renderer;
+use renderer::*;
 
 fn main() {
-    let output_path = r"render.png";
+    // e.g. Some("render.hdr") to also keep the unclamped linear colors
+    let hdr_output_path: Option<&str> = None;
+
+    // Defaults for anything not given on the command line, see --help
+    let defaults = RenderSettings {
+        image_width: 1920,
+        image_height: 1080,
+        // Pixels stop early once their noise is below the tolerance
+        min_samples_per_pixel: 32,
+        samples_per_pixel: 500,
+        sample_tolerance: 0.002,
+        max_depth: 50,
+        seed: 0,
+        // RenderMode::Normals, RenderMode::Depth { .. } or
+        // RenderMode::BoxHeatmap { .. } to debug geometry
+        mode: RenderMode::Shaded,
+        ..RenderSettings::default()
+    };
+    // .ppm writes a binary PPM, other extensions are encoded by the image crate
+    let options = cli::parse_args(std::env::args_os(), &defaults, "render.png")
+        .unwrap_or_else(|err| err.exit());
+
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .unwrap();
+    }
+
+    let preview_scale = options.preview_scale;
+    let mut settings = options.settings;
+    // Never below the 2x2 the camera needs
+    settings.image_width = (settings.image_width / preview_scale).max(2);
+    settings.image_height = (settings.image_height / preview_scale).max(2);
 
-    let aspect_ratio = 16.0 / 9.0;
-    let image_width = 1920;
-    let image_height = (image_width as f64 / aspect_ratio) as u32;
-    let samples_per_pixel = 500;
-    let max_depth = 50;
+    // Use e.g. Color::new(0.0, 0.0, 0.0) for scenes lit only by emitters, or
+    // EnvironmentMap::load("studio.hdr").unwrap() to light the scene with an HDRI
+    let background = Sky;
 
     let test = Color::new(0.8, 0.5, 0.2);
 
     // World
-    let r = f64::cos((PI / 4.0));
+    let r = f64::cos(PI / 4.0);
     let mut world = HittableList::new();
 
     let ground_sphere_pos = Point3::new(0.0, -100.5, -1.0);
     let center_sphere_pos = Point3::new(0.0, 0.0, -1.0);
     let left_sphere_pos = Point3::new(-1.1, 0.0, -1.0);
     let right_sphere_pos = Point3::new(1.1, 0.0, -1.0);
-    
-    let ground_sphere_mat = Rc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 1.0));
-    let center_sphere_mat = Rc::new(Lambertian::new(Color::new(0.0, 1.0, 1.0)));
-    let left_sphere_mat = Rc::new(Dielectric::new(1.5));
-    let right_sphere_mat = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.0));
-    
-    world.add(Rc::new(Sphere::new(
+    let box_min = Point3::new(-0.4, -0.5, -2.8);
+    let box_max = Point3::new(0.4, 0.3, -2.0);
+
+    let ground_sphere_mat = Arc::new(Metal::new(Color::new(0.8, 0.8, 0.8), 1.0));
+    let center_sphere_mat = Arc::new(Lambertian::new(Color::new(0.0, 1.0, 1.0)));
+    let left_sphere_mat = Arc::new(Dielectric::new(1.5));
+    let right_sphere_mat = Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.0));
+    let box_mat = Arc::new(Lambertian::new(Color::new(0.8, 0.2, 0.2)));
+
+    world.add(Arc::new(Sphere::new(
         ground_sphere_pos,
         100.0,
         ground_sphere_mat.clone(),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         center_sphere_pos,
         0.5,
         center_sphere_mat.clone(),
     )));
-    world.add(Rc::new(Sphere::new(
+    world.add(Arc::new(Sphere::new(
         left_sphere_pos,
         0.5,
         left_sphere_mat.clone(),
     )));
-    // world.add(Rc::new(Sphere::new(
-    //     left_sphere_pos,
-    //     -0.4999,
-    //     left_sphere_mat.clone(),
-    // )));
-    world.add(Rc::new(Sphere::new(
+    // A slightly smaller negative-radius sphere inside makes the glass hollow
+    world.add(Arc::new(Sphere::new(
+        left_sphere_pos,
+        -0.4999,
+        left_sphere_mat.clone(),
+    )));
+    world.add(Arc::new(Sphere::new(
         right_sphere_pos,
         0.5,
         right_sphere_mat.clone(),
-    )));    
+    )));
+    world.add(Arc::new(BoxObj::new(box_min, box_max, box_mat.clone())));
 
     // Camera
     let look_from = Point3::new(-3.0, 1.0, 2.0);
     let look_at = Point3::new(0.0, 0.0, -1.0);
-    let up = Vec3::new(0.0, 1.0, 0.0);
-    
-    let focus_dist = (look_from - look_at).length();
-    
-    let camera = Camera::new(
-        &look_from,
-        &look_at,
-        &up,
-        30.0,
-        aspect_ratio,
-        0.1, 
-        focus_dist,
-    );
+
+    let camera = CameraBuilder::new(look_from, look_at)
+        .v_fov(30.0)
+        .image_size(settings.image_width, settings.image_height)
+        .aperture(0.1)
+        .shutter(0.0, 1.0)
+        .build();
 
     // Render
-    let mut image: RgbImage = ImageBuffer::new(image_width, image_height);
-
-    for j in (0..image_height).rev() {
-        println!("Scanlines remaining: {}", j + 1);
-        for i in 0..image_width {
-            let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-            let mut rng = rand::thread_rng();
-            for s in 0..samples_per_pixel {
-                let u = (i as f64 + rng.gen_range(0.0..1.0)) / (image_width - 1) as f64;
-                let v = (j as f64 + rng.gen_range(0.0..1.0)) / (image_height - 1) as f64;
-                let r = camera.get_ray(u, v);
-
-                pixel_color += ray_color(&r, &world, max_depth);
-            }
-            *image.get_pixel_mut(i, image_height - j - 1) =
-                Rgb(pixel_color.as_u8_color(samples_per_pixel));
-        }
-    }
+    let mut renderer = Renderer::from_list(camera, world, settings);
+    renderer.background = Arc::new(background);
+
+    let hdr = renderer.render_hdr();
 
-    image.save(output_path).unwrap();
+    let image = post::upscale(&renderer.tone_map(&hdr), preview_scale);
+
+    output::save_image(&options.output, &image).unwrap();
+    if let Some(hdr_output_path) = hdr_output_path {
+        output::save_hdr(hdr_output_path, &hdr).unwrap();
+    }
 }
@@ -0,0 +1,200 @@
+use crate::la::{Point3, Vec3};
+
+// Affine transform as a row-major 4x4 matrix acting on column vectors, so
+// `a * b` applies b first. The bottom row is always (0, 0, 0, 1), which the
+// constructors and products all keep.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Mat4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    // The linear part as three rows, then the translation
+    pub fn from_parts(rows: [Vec3; 3], offset: Vec3) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for i in 0..3 {
+            m[i] = [rows[i].x(), rows[i].y(), rows[i].z(), offset[i]];
+        }
+        m[3] = [0.0, 0.0, 0.0, 1.0];
+        Mat4 { m }
+    }
+
+    pub fn identity() -> Mat4 {
+        Mat4::scale(Vec3::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn translate(offset: Vec3) -> Mat4 {
+        Mat4::from_parts(
+            [
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ],
+            offset,
+        )
+    }
+
+    pub fn scale(s: Vec3) -> Mat4 {
+        Mat4::from_parts(
+            [
+                Vec3::new(s.x(), 0.0, 0.0),
+                Vec3::new(0.0, s.y(), 0.0),
+                Vec3::new(0.0, 0.0, s.z()),
+            ],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    // Rotations take degrees and turn counter-clockwise looking down the
+    // axis towards the origin
+    pub fn rotate_x(angle: f64) -> Mat4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Mat4::from_parts(
+            [
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, cos, -sin),
+                Vec3::new(0.0, sin, cos),
+            ],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn rotate_y(angle: f64) -> Mat4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Mat4::from_parts(
+            [
+                Vec3::new(cos, 0.0, sin),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec3::new(-sin, 0.0, cos),
+            ],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn rotate_z(angle: f64) -> Mat4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Mat4::from_parts(
+            [
+                Vec3::new(cos, -sin, 0.0),
+                Vec3::new(sin, cos, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ],
+            Vec3::new(0.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.m[row][col]
+    }
+
+    fn row(&self, i: usize) -> Vec3 {
+        Vec3::new(self.m[i][0], self.m[i][1], self.m[i][2])
+    }
+
+    fn offset(&self) -> Vec3 {
+        Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        self.transform_vector(p) + self.offset()
+    }
+
+    // Directions ignore the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            Vec3::dot(&self.row(0), v),
+            Vec3::dot(&self.row(1), v),
+            Vec3::dot(&self.row(2), v),
+        )
+    }
+
+    // Through the inverse transpose, so normals stay perpendicular to the
+    // transformed surface. Not normalized.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        let [r0, r1, r2] = [self.row(0), self.row(1), self.row(2)];
+        Vec3::new(
+            Vec3::dot(&Vec3::cross(&r1, &r2), n),
+            Vec3::dot(&Vec3::cross(&r2, &r0), n),
+            Vec3::dot(&Vec3::cross(&r0, &r1), n),
+        ) / self.determinant()
+    }
+
+    // Of the linear part
+    pub fn determinant(&self) -> f64 {
+        Vec3::dot(&self.row(0), &Vec3::cross(&self.row(1), &self.row(2)))
+    }
+
+    // None for singular transforms
+    pub fn inverse(&self) -> Option<Mat4> {
+        let det = self.determinant();
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        // The columns of the linear part's inverse are the cross products of
+        // its rows, and the translation is undone after it
+        let [r0, r1, r2] = [self.row(0), self.row(1), self.row(2)];
+        let columns = [
+            Vec3::cross(&r1, &r2) / det,
+            Vec3::cross(&r2, &r0) / det,
+            Vec3::cross(&r0, &r1) / det,
+        ];
+        let rows = [0, 1, 2].map(|i| Vec3::new(columns[0][i], columns[1][i], columns[2][i]));
+        let linear = Mat4::from_parts(rows, Vec3::new(0.0, 0.0, 0.0));
+        Some(Mat4::from_parts(
+            rows,
+            -linear.transform_vector(&self.offset()),
+        ))
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::assert_vec3_eq;
+
+    #[test]
+    fn composition_applies_the_right_factor_first() {
+        let translate = Mat4::translate(Vec3::new(1.0, 2.0, 3.0));
+        let rotate = Mat4::rotate_y(90.0);
+        let p = Point3::new(1.0, 0.0, 0.0);
+
+        let in_order = rotate.transform_point(&translate.transform_point(&p));
+        assert_vec3_eq!((rotate * translate).transform_point(&p), in_order, 1e-12);
+        // Rotating (2, 2, 3) a quarter turn about y gives (3, 2, -2)
+        assert_vec3_eq!(in_order, Point3::new(3.0, 2.0, -2.0), 1e-12);
+    }
+
+    #[test]
+    fn transformed_normals_stay_perpendicular_to_transformed_tangents() {
+        let m = Mat4::rotate_x(30.0)
+            * Mat4::scale(Vec3::new(3.0, 0.5, 1.0))
+            * Mat4::rotate_z(45.0)
+            * Mat4::translate(Vec3::new(-1.0, 4.0, 2.0));
+        let n = Vec3::new(1.0, 1.0, 1.0);
+        for tangent in [Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 2.0, -2.0)] {
+            let dot = Vec3::dot(&m.transform_normal(&n), &m.transform_vector(&tangent));
+            assert!(dot.abs() < 1e-9, "{}", dot);
+        }
+        // A plain transform_vector would tip it off the surface
+        let tipped = Vec3::dot(
+            &m.transform_vector(&n),
+            &m.transform_vector(&Vec3::new(0.0, 2.0, -2.0)),
+        );
+        assert!(tipped.abs() > 0.1, "{}", tipped);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::la::{Point3, Vec3};
+use crate::objects::{Hittable, HittableList, Material, Triangle};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Reads the `v` and `f` lines of a Wavefront OBJ file, fan-triangulating
+// polygons. Every other directive (normals, texcoords, groups...) is skipped.
+pub fn load_obj<P: AsRef<Path>>(path: P, mat: Arc<dyn Material>) -> io::Result<HittableList> {
+    parse_obj(&fs::read_to_string(path)?, mat)
+}
+
+pub fn parse_obj(source: &str, mat: Arc<dyn Material>) -> io::Result<HittableList> {
+    let mut vertices: Vec<Point3> = Vec::new();
+    let mut triangles = HittableList::new();
+
+    for (line_no, line) in source.lines().enumerate() {
+        let invalid = |msg: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {}", line_no + 1, msg),
+            )
+        };
+
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let coords: Vec<f64> = tokens
+                    .take(3)
+                    .map(|t| t.parse::<f64>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid("bad vertex coordinate"))?;
+                if coords.len() != 3 {
+                    return Err(invalid("vertex needs three coordinates"));
+                }
+                vertices.push(Point3::new(coords[0], coords[1], coords[2]));
+            }
+            Some("f") => {
+                let face: Vec<Point3> = tokens
+                    .map(|t| {
+                        // Only the position index matters: "7", "7/1", "7//3", "7/1/3"
+                        let index: i64 = t
+                            .split('/')
+                            .next()
+                            .unwrap()
+                            .parse()
+                            .map_err(|_| invalid("bad face index"))?;
+                        // OBJ indices are 1-based, negative ones count back from the end
+                        let index = if index < 0 {
+                            vertices.len() as i64 + index
+                        } else {
+                            index - 1
+                        };
+                        usize::try_from(index)
+                            .ok()
+                            .and_then(|i| vertices.get(i))
+                            .copied()
+                            .ok_or_else(|| invalid("face index out of range"))
+                    })
+                    .collect::<Result<_, _>>()?;
+                if face.len() < 3 {
+                    return Err(invalid("face needs at least three vertices"));
+                }
+
+                for i in 1..face.len() - 1 {
+                    triangles.add(Arc::new(Triangle::new(
+                        face[0],
+                        face[i],
+                        face[i + 1],
+                        mat.clone(),
+                    )));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(triangles)
+}
+
+// An icosahedron split `subdivisions` times, each split turning a triangle
+// into four with the new corners pushed out onto the sphere. Gives
+// 20 * 4^subdivisions triangles, wound so their normals face outwards.
+pub fn tessellate_sphere(
+    center: Point3,
+    radius: f64,
+    subdivisions: u32,
+    mat: Arc<dyn Material>,
+) -> HittableList {
+    let t = (1.0 + 5.0_f64.sqrt()) / 2.0;
+    let vertices = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .map(|(x, y, z)| Vec3::new(x, y, z).as_unit_vector());
+    let indices = [
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    // Faces are kept as unit vectors until the end
+    let mut faces: Vec<[Vec3; 3]> = indices.iter().map(|f| f.map(|i| vertices[i])).collect();
+    for _ in 0..subdivisions {
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = (a + b).as_unit_vector();
+                let bc = (b + c).as_unit_vector();
+                let ca = (c + a).as_unit_vector();
+                [[a, ab, ca], [ab, b, bc], [ca, bc, c], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    faces
+        .iter()
+        .map(|face| {
+            let [v0, v1, v2] = face.map(|v| center + radius * v);
+            Arc::new(Triangle::new(v0, v1, v2, mat.clone())) as Arc<dyn Hittable>
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::{Color, Ray};
+    use crate::objects::{Lambertian, Sphere};
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    // A 2 x 2 x 2 cube with quad faces
+    const CUBE_OBJ: &str = "\
+# cube
+v -1 -1 -1
+v 1 -1 -1
+v 1 1 -1
+v -1 1 -1
+v -1 -1 1
+v 1 -1 1
+v 1 1 1
+v -1 1 1
+vn 0 0 1
+f 1 2 3 4
+f 5 8 7 6
+f 1 5 6 2
+f 2 6 7 3
+f 3 7 8 4
+f 5 1 4 8
+";
+
+    #[test]
+    fn obj_cube_loads_as_twelve_triangles() {
+        let path =
+            std::env::temp_dir().join(format!("ray_tracing_cube_{}.obj", std::process::id()));
+        fs::write(&path, CUBE_OBJ).unwrap();
+        let cube = load_obj(&path, gray());
+        fs::remove_file(&path).unwrap();
+        let cube = cube.unwrap();
+
+        assert_eq!(cube.objects().len(), 12);
+        // Triangle boxes are padded a little
+        let bbox = cube.bounding_box().unwrap();
+        assert!((bbox.min - Point3::new(-1.0, -1.0, -1.0)).length() < 1e-3);
+        assert!((bbox.max - Point3::new(1.0, 1.0, 1.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn finely_tessellated_sphere_hits_close_to_the_analytic_one() {
+        let center = Point3::new(1.0, -2.0, 0.5);
+        let mesh = tessellate_sphere(center, 2.0, 4, gray());
+        let sphere = Sphere::new(center, 2.0, gray());
+        let mut rng = SmallRng::seed_from_u64(15);
+        for _ in 0..50 {
+            // Towards a point near the center from well outside
+            let origin = center + 10.0 * Vec3::rand_unit_vector(&mut rng);
+            let target = center + 0.5 * Vec3::rand_unit_vector(&mut rng);
+            let r = Ray::new(origin, target - origin);
+
+            let on_mesh = mesh.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let on_sphere = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let distance = (on_mesh.p - center).length();
+            // Flat faces sit inside the sphere, by at most the sagitta
+            assert!((1.99..=2.0 + 1e-9).contains(&distance), "{}", distance);
+            assert!((on_mesh.p - on_sphere.p).length() < 0.05);
+        }
+    }
+}
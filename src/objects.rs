@@ -1,13 +1,26 @@
-use crate::la::{Color, Point3, Ray, Vec3};
-use std::rc::Rc;
+use crate::aabb::Aabb;
+use crate::la::{splitmix64, Color, Onb, Point3, Ray, Vec3};
+use crate::pdf::{CosinePdf, Pdf, SpherePdf};
+use crate::stats;
+use crate::texture::{SolidColor, Texture};
+use rand::rngs::SmallRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
 use std::vec::Vec;
 
 pub struct HitRecord {
     pub t: f64,
     pub p: Point3,
+    pub u: f64,
+    pub v: f64,
     pub normal: Vec3,
-    pub mat: Rc<dyn Material>,
+    pub mat: Arc<dyn Material>,
     pub front_face: bool,
+    // Surface direction of increasing u, for objects that know it. Anisotropic
+    // and normal-mapped materials fall back to an arbitrary tangent without one.
+    pub tangent: Option<Vec3>,
 }
 
 impl HitRecord {
@@ -15,7 +28,9 @@ impl HitRecord {
         r: &Ray,
         t: f64,
         p: Point3,
-        mat: Rc<dyn Material>,
+        u: f64,
+        v: f64,
+        mat: Arc<dyn Material>,
         outward_normal: Vec3,
     ) -> HitRecord {
         let front_face = Vec3::dot(&r.direction(), &outward_normal) < 0.0;
@@ -28,25 +43,67 @@ impl HitRecord {
         HitRecord {
             t,
             p,
+            u,
+            v,
             normal,
             mat,
             front_face,
+            tangent: None,
         }
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
+    // The nearest hit with t in [t_min, t_max], if any
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    // None for unbounded objects
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // Whether pdf_value() and random() are implemented, so the object can be
+    // sampled as a light. Lights that can't be are left out of light
+    // sampling, and bounces fall back to the material's own distribution.
+    fn can_sample(&self) -> bool {
+        false
+    }
+
+    // Density (per solid angle) of random() picking `direction` from `origin`.
+    // Only meaningful when can_sample() is true.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // Direction from `origin` towards a random point on the object, not
+    // necessarily of unit length. Only meaningful when can_sample() is true.
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    // Short type name, e.g. "Sphere", for scene summaries
+    fn type_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        let name = name.split('<').next().unwrap_or(name);
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    // How many bounding boxes `r` passes through within this object, for
+    // RenderMode::BoxHeatmap. Plain objects only count their own box.
+    fn box_hits(&self, r: &Ray, t_min: f64, t_max: f64) -> u32 {
+        match self.bounding_box() {
+            Some(bbox) if bbox.hit(r, t_min, t_max) => 1,
+            _ => 0,
+        }
+    }
 }
 
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    pub mat: Rc<dyn Material>,
+    pub mat: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Point3, radius: f64, mat: Rc<dyn Material>) -> Sphere {
+    pub fn new(center: Point3, radius: f64, mat: Arc<dyn Material>) -> Sphere {
         Sphere {
             center,
             radius,
@@ -57,171 +114,2295 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin() - self.center;
-        let a = Vec3::dot(&r.direction(), &r.direction());
-        let hb = Vec3::dot(&oc, &r.direction());
-        let c = Vec3::dot(&oc, &oc) - self.radius.powi(2);
-        let discriminant = hb.powi(2) - a * c;
-        if discriminant < 0.0 {
-            return None;
+        stats::count_hit_call();
+        hit_sphere(&self.center, self.radius, &self.mat, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(&self.center, self.radius))
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    // Uniform over the cone of directions the sphere covers, or over all
+    // directions from inside it
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let distance_squared = (self.center - *origin).length_squared();
+        if distance_squared <= self.radius.powi(2) {
+            return 1.0 / (4.0 * PI);
+        }
+        if self
+            .hit(&Ray::new(*origin, *direction), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
         }
 
-        let sqrtd = discriminant.sqrt();
-        let mut t = (-hb - sqrtd) / a;
-        // Check for closest hit
-        if (t < t_min || t_max < t) {
-            t = (-hb + sqrtd) / a;
-            if (t < t_min || t_max < t) {
-                return None;
-            }
+        let cos_theta_max = f64::sqrt(1.0 - self.radius.powi(2) / distance_squared);
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let direction = self.center - *origin;
+        let distance_squared = direction.length_squared();
+        if distance_squared <= self.radius.powi(2) {
+            return Vec3::rand_unit_vector(rng);
         }
 
-        let p = r.at(t);
-        let outward_normal = (p - self.center) / self.radius;
-        let mut rec = HitRecord::new(r, t, p, self.mat.clone(), outward_normal);
+        let cos_theta_max = f64::sqrt(1.0 - self.radius.powi(2) / distance_squared);
+        let z = 1.0 + rng.gen::<f64>() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let sin_theta = f64::sqrt(1.0 - z.powi(2));
 
-        return Some(rec);
+        // Sampled around the direction to the center
+        Onb::from_w(&direction).local(&Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), z))
     }
 }
 
-#[derive(Clone)]
-pub struct HittableList {
-    objects: Vec<Rc<dyn Hittable>>,
+pub struct MovingSphere {
+    pub center0: Point3,
+    pub center1: Point3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub mat: Arc<dyn Material>,
 }
 
-impl HittableList {
-    pub fn new() -> HittableList {
-        HittableList {
-            objects: Vec::<Rc<dyn Hittable>>::new(),
+impl MovingSphere {
+    pub fn new(
+        center0: Point3,
+        center1: Point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        mat: Arc<dyn Material>,
+    ) -> MovingSphere {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            mat,
         }
     }
 
-    pub fn add(&mut self, object: Rc<dyn Hittable>) -> () {
-        self.objects.push(object);
+    pub fn center(&self, time: f64) -> Point3 {
+        let t = (time - self.time0) / (self.time1 - self.time0);
+        self.center0.lerp(&self.center1, t)
     }
+}
 
-    pub fn clear(&mut self) {
-        self.objects.clear();
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        hit_sphere(
+            &self.center(r.time()),
+            self.radius,
+            &self.mat,
+            r,
+            t_min,
+            t_max,
+        )
     }
 
-    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut hit_anything = false;
-        let mut closest = t_max;
+    fn bounding_box(&self) -> Option<Aabb> {
+        let box0 = sphere_box(&self.center(self.time0), self.radius);
+        let box1 = sphere_box(&self.center(self.time1), self.radius);
+        Some(Aabb::surrounding(&box0, &box1))
+    }
+}
 
-        let mut closest_rec: Option<HitRecord> = None;
-        for object in &self.objects {
-            match object.hit(r, t_min, closest) {
-                Some(rec) => {
-                    closest = rec.t;
-                    closest_rec = Some(rec);
-                }
-                None => (),
-            }
-        }
+fn hit_sphere(
+    center: &Point3,
+    radius: f64,
+    mat: &Arc<dyn Material>,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord> {
+    // Ray directions aren't normalized (scaling, motion and the camera all
+    // produce arbitrary lengths, and t is measured in those units), so `a`
+    // can't be assumed to be 1
+    let oc = r.origin() - *center;
+    let a = r.direction().length_squared();
+    let hb = Vec3::dot(&oc, &r.direction());
+    let c = Vec3::dot(&oc, &oc) - radius.powi(2);
+    let discriminant = hb.powi(2) - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    // Roots via q = -(hb + sign(hb) * sqrtd), which avoids the cancellation
+    // -hb + sqrtd suffers when the origin is far away or on the surface
+    let sqrtd = discriminant.sqrt();
+    let q = -(hb + hb.signum() * sqrtd);
+    let (t0, t1) = (q / a, c / q);
+    let (near, far) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+
+    // The nearest root in range. From inside the sphere (or a negative-radius
+    // one) the near root is behind the origin and the exit is the far one.
+    let t = [near, far]
+        .into_iter()
+        .find(|t| (t_min..=t_max).contains(t))?;
 
-        return closest_rec;
+    let p = r.at(t);
+    let outward_normal = (p - *center) / radius;
+    let local = (p - *center) / radius.abs();
+    let (u, v) = sphere_uv(&local);
+    let mut rec = HitRecord::new(r, t, p, u, v, mat.clone(), outward_normal);
+
+    // u runs around y, so its direction vanishes at the poles
+    let tangent = Vec3::new(local.z(), 0.0, -local.x());
+    if !tangent.is_near_zero() {
+        rec.tangent = Some(tangent.as_unit_vector());
     }
+    Some(rec)
 }
 
-pub trait Material {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+// Maps a point on the unit sphere to (u, v) in [0, 1]: u from the azimuth
+// around y (starting at -x), v from the polar angle (0 at -y, 1 at +y)
+fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = f64::acos(-p.y());
+    let phi = f64::atan2(-p.z(), p.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
 }
 
-pub struct Lambertian {
-    albedo: Color,
+fn sphere_box(center: &Point3, radius: f64) -> Aabb {
+    let r = Vec3::new(radius.abs(), radius.abs(), radius.abs());
+    Aabb::new(*center - r, *center + r)
 }
 
-impl Lambertian {
-    pub fn new(albedo: Color) -> Lambertian {
-        Lambertian { albedo }
+pub struct XyRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub y0: f64,
+    pub y1: f64,
+    pub k: f64,
+    pub mat: Arc<dyn Material>,
+}
+
+impl XyRect {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mat: Arc<dyn Material>) -> XyRect {
+        XyRect {
+            x0,
+            x1,
+            y0,
+            y1,
+            k,
+            mat,
+        }
     }
 }
 
-impl Material for Lambertian {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let mut scatter_dir = rec.normal + Vec3::rand_unit_vector();
+impl Hittable for XyRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        hit_rect(
+            r,
+            (t_min, t_max),
+            [0, 1, 2],
+            [self.x0, self.x1, self.y0, self.y1, self.k],
+            Vec3::new(0.0, 0.0, 1.0),
+            &self.mat,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            [0, 1, 2],
+            [self.x0, self.x1, self.y0, self.y1, self.k],
+        ))
+    }
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        area_pdf_value(self, area, origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        rect_random(
+            [0, 1, 2],
+            [self.x0, self.x1, self.y0, self.y1, self.k],
+            origin,
+            rng,
+        )
+    }
+}
+
+pub struct XzRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub mat: Arc<dyn Material>,
+}
 
-        if (scatter_dir.is_near_zero()) {
-            scatter_dir = rec.normal;
+impl XzRect {
+    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Material>) -> XzRect {
+        XzRect {
+            x0,
+            x1,
+            z0,
+            z1,
+            k,
+            mat,
         }
+    }
+}
+
+impl Hittable for XzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        hit_rect(
+            r,
+            (t_min, t_max),
+            [0, 2, 1],
+            [self.x0, self.x1, self.z0, self.z1, self.k],
+            Vec3::new(0.0, 1.0, 0.0),
+            &self.mat,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            [0, 2, 1],
+            [self.x0, self.x1, self.z0, self.z1, self.k],
+        ))
+    }
+    fn can_sample(&self) -> bool {
+        true
+    }
 
-        let r_scattered = Ray::new(rec.p, scatter_dir);
-        let attenuation = self.albedo;
-        return Some((r_scattered, attenuation));
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        area_pdf_value(self, area, origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        rect_random(
+            [0, 2, 1],
+            [self.x0, self.x1, self.z0, self.z1, self.k],
+            origin,
+            rng,
+        )
     }
 }
 
-pub struct Metal {
-    albedo: Color,
-    roughness: f64,
+pub struct YzRect {
+    pub y0: f64,
+    pub y1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub mat: Arc<dyn Material>,
 }
 
-impl Metal {
-    pub fn new(albedo: Color, roughness: f64) -> Metal {
-        Metal {
-            albedo,
-            roughness: roughness.clamp(0.0, 1.0),
+impl YzRect {
+    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mat: Arc<dyn Material>) -> YzRect {
+        YzRect {
+            y0,
+            y1,
+            z0,
+            z1,
+            k,
+            mat,
         }
     }
 }
 
-impl Material for Metal {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let reflected_dir = Vec3::reflect(&r.direction(), &rec.normal);
+impl Hittable for YzRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        hit_rect(
+            r,
+            (t_min, t_max),
+            [1, 2, 0],
+            [self.y0, self.y1, self.z0, self.z1, self.k],
+            Vec3::new(1.0, 0.0, 0.0),
+            &self.mat,
+        )
+    }
 
-        let r_scattered = Ray::new(
-            rec.p,
-            reflected_dir + self.roughness * Vec3::rand_unit_vector(),
-        );
-        let attenuation = self.albedo;
-        if (Vec3::dot(&r_scattered.direction(), &rec.normal) > 0.0) {
-            return Some((r_scattered, attenuation));
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(rect_box(
+            [1, 2, 0],
+            [self.y0, self.y1, self.z0, self.z1, self.k],
+        ))
+    }
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        area_pdf_value(self, area, origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        rect_random(
+            [1, 2, 0],
+            [self.y0, self.y1, self.z0, self.z1, self.k],
+            origin,
+            rng,
+        )
+    }
+}
+
+pub struct BoxObj {
+    pub min: Point3,
+    pub max: Point3,
+    sides: HittableList,
+}
+
+impl BoxObj {
+    pub fn new(min: Point3, max: Point3, mat: Arc<dyn Material>) -> BoxObj {
+        let mut sides = HittableList::new();
+
+        sides.add(Arc::new(XyRect::new(
+            min.x(),
+            max.x(),
+            min.y(),
+            max.y(),
+            max.z(),
+            mat.clone(),
+        )));
+        sides.add(Arc::new(XyRect::new(
+            min.x(),
+            max.x(),
+            min.y(),
+            max.y(),
+            min.z(),
+            mat.clone(),
+        )));
+        sides.add(Arc::new(XzRect::new(
+            min.x(),
+            max.x(),
+            min.z(),
+            max.z(),
+            max.y(),
+            mat.clone(),
+        )));
+        sides.add(Arc::new(XzRect::new(
+            min.x(),
+            max.x(),
+            min.z(),
+            max.z(),
+            min.y(),
+            mat.clone(),
+        )));
+        sides.add(Arc::new(YzRect::new(
+            min.y(),
+            max.y(),
+            min.z(),
+            max.z(),
+            max.x(),
+            mat.clone(),
+        )));
+        sides.add(Arc::new(YzRect::new(
+            min.y(),
+            max.y(),
+            min.z(),
+            max.z(),
+            min.x(),
+            mat,
+        )));
+
+        BoxObj { min, max, sides }
+    }
+}
+
+impl Hittable for BoxObj {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let rec = self.sides.hit(r, t_min, t_max)?;
+
+        // The rects' normals all face +axis, so point the min faces back out of the box
+        let center = 0.5 * (self.min + self.max);
+        let axis = (0..3).find(|&a| rec.normal[a] != 0.0).unwrap();
+        let mut outward_normal = Vec3::new(0.0, 0.0, 0.0);
+        outward_normal[axis] = if rec.p[axis] < center[axis] {
+            -1.0
         } else {
+            1.0
+        };
+
+        Some(HitRecord::new(
+            r,
+            rec.t,
+            rec.p,
+            rec.u,
+            rec.v,
+            rec.mat,
+            outward_normal,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        self.sides.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        self.sides.random(origin, rng)
+    }
+}
+
+pub struct Triangle {
+    pub v0: Point3,
+    pub v1: Point3,
+    pub v2: Point3,
+    pub mat: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, mat: Arc<dyn Material>) -> Triangle {
+        Triangle { v0, v1, v2, mat }
+    }
+
+    pub fn area(&self) -> f64 {
+        0.5 * Vec3::cross(&(self.v1 - self.v0), &(self.v2 - self.v0)).length()
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller–Trumbore, (u, v) are the barycentric weights of v1 and v2
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+
+        let pvec = Vec3::cross(&r.direction(), &edge2);
+        let det = Vec3::dot(&edge1, &pvec);
+        // Ray is parallel to the triangle
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - self.v0;
+        let u = Vec3::dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = Vec3::cross(&tvec, &edge1);
+        let v = Vec3::dot(&r.direction(), &qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = Vec3::dot(&edge2, &qvec) * inv_det;
+        if t < t_min || t_max < t {
             return None;
         }
+
+        let outward_normal = Vec3::cross(&edge1, &edge2).as_unit_vector();
+        Some(HitRecord::new(
+            r,
+            t,
+            r.at(t),
+            u,
+            v,
+            self.mat.clone(),
+            outward_normal,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad so axis-aligned triangles don't get a flat box
+        let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+        let min = self.v0.min(&self.v1).min(&self.v2);
+        let max = self.v0.max(&self.v1).max(&self.v2);
+        Some(Aabb::new(min - pad, max + pad))
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        area_pdf_value(self, self.area(), origin, direction)
+    }
+
+    // Folding the unit square's upper half back over the diagonal keeps
+    // points uniform over the triangle
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let (mut a, mut b) = (rng.gen::<f64>(), rng.gen::<f64>());
+        if a + b > 1.0 {
+            (a, b) = (1.0 - a, 1.0 - b);
+        }
+        let p = self.v0 + a * (self.v1 - self.v0) + b * (self.v2 - self.v0);
+        p - *origin
     }
 }
 
-pub struct Dielectric {
-    pub ior: f64,
+// Parallelogram with corner `q` and edges `u` and `v`, facing u x v
+pub struct Quad {
+    pub q: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub mat: Arc<dyn Material>,
 }
 
-impl Dielectric {
-    pub fn new(ior: f64) -> Self {
-        Dielectric { ior }
+impl Quad {
+    pub fn new(q: Point3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) -> Quad {
+        Quad { q, u, v, mat }
     }
 
-    pub fn reflectance(cosine: f64, ior: f64) -> f64 {
-        // Schlick's approximation
-        let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
-        return r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
+    pub fn area(&self) -> f64 {
+        Vec3::cross(&self.u, &self.v).length()
     }
 }
 
-impl Material for Dielectric {
-    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ior
-        } else {
-            self.ior
-        };
+impl Hittable for Quad {
+    // (u, v) are the hit's coordinates along the two edges, both in [0, 1]
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        let n = Vec3::cross(&self.u, &self.v);
+        let normal = n.as_unit_vector();
 
-        let cos_theta = Vec3::dot(&(-r.direction()), &rec.normal).min(1.0);
-        let sin_theta = f64::sqrt(1.0 - cos_theta.powi(2));
+        let denom = Vec3::dot(&normal, &r.direction());
+        // Ray is parallel to the plane
+        if denom.abs() < 1e-12 {
+            return None;
+        }
 
-        let total_internal_reflection = refraction_ratio * sin_theta > 1.0;
+        let t = Vec3::dot(&normal, &(self.q - r.origin())) / denom;
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
 
-        let r_direction = if (total_internal_reflection
-            || (Dielectric::reflectance(cos_theta, refraction_ratio)) > rand::random::<f64>())
-        {
-            r.direction().reflect(&rec.normal)
+        // Coordinates of the hit in the (u, v) basis of the plane
+        let p = r.at(t);
+        let planar = p - self.q;
+        let w = n / Vec3::dot(&n, &n);
+        let alpha = Vec3::dot(&w, &Vec3::cross(&planar, &self.v));
+        let beta = Vec3::dot(&w, &Vec3::cross(&self.u, &planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord::new(
+            r,
+            t,
+            p,
+            alpha,
+            beta,
+            self.mat.clone(),
+            normal,
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad so axis-aligned quads don't get a flat box
+        let pad = Vec3::new(0.0001, 0.0001, 0.0001);
+        let corners = [self.q + self.u, self.q + self.v, self.q + self.u + self.v];
+        let min = corners.iter().fold(self.q, |min, c| min.min(c));
+        let max = corners.iter().fold(self.q, |max, c| max.max(c));
+        Some(Aabb::new(min - pad, max + pad))
+    }
+
+    fn can_sample(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        area_pdf_value(self, self.area(), origin, direction)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let p = self.q + rng.gen::<f64>() * self.u + rng.gen::<f64>() * self.v;
+        p - *origin
+    }
+}
+
+// Infinite plane through `point`, facing `normal`
+pub struct Plane {
+    pub point: Point3,
+    pub normal: Vec3,
+    pub mat: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vec3, mat: Arc<dyn Material>) -> Plane {
+        Plane {
+            point,
+            normal: normal.as_unit_vector(),
+            mat,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        stats::count_hit_call();
+        let denom = Vec3::dot(&self.normal, &r.direction());
+        // Ray runs parallel to the plane
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = Vec3::dot(&(self.point - r.origin()), &self.normal) / denom;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        // (u, v) repeat every unit along two axes spanning the plane
+        let p = r.at(t);
+        let helper = if self.normal.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
         } else {
-            r.direction().refract(&rec.normal, refraction_ratio)
+            Vec3::new(1.0, 0.0, 0.0)
         };
+        let tangent = Vec3::cross(&helper, &self.normal).as_unit_vector();
+        let bitangent = Vec3::cross(&self.normal, &tangent);
+        let d = p - self.point;
+        let u = Vec3::dot(&d, &tangent).rem_euclid(1.0);
+        let v = Vec3::dot(&d, &bitangent).rem_euclid(1.0);
+
+        Some(HitRecord::new(r, t, p, u, v, self.mat.clone(), self.normal))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Color) -> ConstantMedium {
+        ConstantMedium::from_texture(boundary, density, Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        albedo: Arc<dyn Texture>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: Arc::new(Isotropic::from_texture(albedo)),
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Entry and exit of the boundary along the whole ray, then clip to [t_min, t_max]
+        let rec1 = self.boundary.hit(r, -f64::INFINITY, f64::INFINITY)?;
+        let rec2 = self.boundary.hit(r, rec1.t + 0.0001, f64::INFINITY)?;
+
+        let t1 = rec1.t.max(t_min).max(0.0);
+        let t2 = rec2.t.min(t_max);
+        if t1 >= t2 {
+            return None;
+        }
+
+        let ray_length = r.direction().length();
+        let distance_inside = (t2 - t1) * ray_length;
+        let hit_distance = self.neg_inv_density * f64::ln(ray_rng(r).gen::<f64>());
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        // Normal and face are meaningless inside a volume, any value will do
+        let t = t1 + hit_distance / ray_length;
+        Some(HitRecord::new(
+            r,
+            t,
+            r.at(t),
+            0.0,
+            0.0,
+            self.phase_function.clone(),
+            Vec3::new(1.0, 0.0, 0.0),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+// hit() has no RNG of its own, so seed one from the ray itself. Seeded renders
+// produce the same rays and therefore stay reproducible.
+fn ray_rng(r: &Ray) -> SmallRng {
+    let mut seed = 0;
+    for a in 0..3 {
+        seed = splitmix64(seed ^ r.origin()[a].to_bits());
+        seed = splitmix64(seed ^ r.direction()[a].to_bits());
+    }
+    SmallRng::seed_from_u64(splitmix64(seed ^ r.time().to_bits()))
+}
+
+// Shared intersection for the axis-aligned rects. `axes` holds the two in-plane
+// axes followed by the plane's normal axis, `bounds` is [a0, a1, b0, b1, k].
+fn hit_rect(
+    r: &Ray,
+    (t_min, t_max): (f64, f64),
+    [a, b, n]: [usize; 3],
+    [a0, a1, b0, b1, k]: [f64; 5],
+    outward_normal: Vec3,
+    mat: &Arc<dyn Material>,
+) -> Option<HitRecord> {
+    let t = (k - r.origin()[n]) / r.direction()[n];
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+
+    let p = r.at(t);
+    if p[a] < a0 || p[a] > a1 || p[b] < b0 || p[b] > b1 {
+        return None;
+    }
+
+    let u = (p[a] - a0) / (a1 - a0);
+    let v = (p[b] - b0) / (b1 - b0);
+    Some(HitRecord::new(r, t, p, u, v, mat.clone(), outward_normal))
+}
 
-        let r_scattered = Ray::new(rec.p, r_direction);
+// Rects are flat, so pad the normal axis to keep the box from being degenerate
+fn rect_box([a, b, n]: [usize; 3], [a0, a1, b0, b1, k]: [f64; 5]) -> Aabb {
+    let pad = 0.0001;
+    let mut min = Point3::new(0.0, 0.0, 0.0);
+    let mut max = Point3::new(0.0, 0.0, 0.0);
+    min[a] = a0;
+    max[a] = a1;
+    min[b] = b0;
+    max[b] = b1;
+    min[n] = k - pad;
+    max[n] = k + pad;
+    Aabb::new(min, max)
+}
+
+// A point picked uniformly over the rect, as seen from `origin`
+fn rect_random(
+    [a, b, n]: [usize; 3],
+    [a0, a1, b0, b1, k]: [f64; 5],
+    origin: &Point3,
+    rng: &mut dyn RngCore,
+) -> Vec3 {
+    let mut p = Point3::new(0.0, 0.0, 0.0);
+    p[a] = a0 + rng.gen::<f64>() * (a1 - a0);
+    p[b] = b0 + rng.gen::<f64>() * (b1 - b0);
+    p[n] = k;
+    p - *origin
+}
+
+// Converts a uniform density over an object's area into one per solid angle
+// at `origin`
+fn area_pdf_value(object: &dyn Hittable, area: f64, origin: &Point3, direction: &Vec3) -> f64 {
+    let rec = match object.hit(&Ray::new(*origin, *direction), 0.001, f64::INFINITY) {
+        Some(rec) => rec,
+        None => return 0.0,
+    };
 
-        return Some((r_scattered, attenuation));
+    let distance_squared = rec.t.powi(2) * direction.length_squared();
+    let cosine = (Vec3::dot(direction, &rec.normal) / direction.length()).abs();
+    distance_squared / (cosine * area)
+}
+
+#[derive(Clone)]
+pub struct HittableList {
+    objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl HittableList {
+    pub fn new() -> HittableList {
+        HittableList {
+            objects: Vec::<Arc<dyn Hittable>>::new(),
+        }
+    }
+
+    pub fn with_objects(objects: Vec<Arc<dyn Hittable>>) -> HittableList {
+        HittableList { objects }
+    }
+
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
+        self.objects.push(object);
+    }
+
+    // Shifts the objects after `index` down by one. Panics if out of bounds.
+    pub fn remove(&mut self, index: usize) -> Arc<dyn Hittable> {
+        self.objects.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Arc<dyn Hittable>> {
+        self.objects.get(index)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Arc<dyn Hittable>> {
+        self.objects.iter()
+    }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    // Counts and bounds of the objects, to sanity-check imports before a
+    // long render. Nested lists and BVHs count as one object each.
+    pub fn summary(&self) -> SceneSummary {
+        let mut summary = SceneSummary {
+            objects: self.objects.len(),
+            ..SceneSummary::default()
+        };
+        for object in &self.objects {
+            *summary.by_type.entry(object.type_name()).or_insert(0) += 1;
+            match (object.bounding_box(), summary.bbox) {
+                (Some(bbox), Some(total)) => summary.bbox = Some(Aabb::surrounding(&total, &bbox)),
+                (Some(bbox), None) => summary.bbox = Some(bbox),
+                (None, _) => summary.unbounded += 1,
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SceneSummary {
+    pub objects: usize,
+    // Around the bounded objects, None if there are none
+    pub bbox: Option<Aabb>,
+    // Planes and other objects without a bounding box
+    pub unbounded: usize,
+    pub by_type: BTreeMap<&'static str, usize>,
+}
+
+impl FromIterator<Arc<dyn Hittable>> for HittableList {
+    fn from_iter<I: IntoIterator<Item = Arc<dyn Hittable>>>(iter: I) -> HittableList {
+        HittableList::with_objects(iter.into_iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for &'a HittableList {
+    type Item = &'a Arc<dyn Hittable>;
+    type IntoIter = std::slice::Iter<'a, Arc<dyn Hittable>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl Extend<Arc<dyn Hittable>> for HittableList {
+    fn extend<I: IntoIterator<Item = Arc<dyn Hittable>>>(&mut self, iter: I) {
+        self.objects.extend(iter);
+    }
+}
+
+impl Hittable for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut closest = t_max;
+        let mut closest_rec: Option<HitRecord> = None;
+
+        // Shrinking t_max to the best hit so far means any later hit is nearer
+        for object in &self.objects {
+            if let Some(rec) = object.hit(r, t_min, closest) {
+                debug_assert!(rec.t >= t_min && rec.t <= closest);
+                closest = rec.t;
+                closest_rec = Some(rec);
+            }
+        }
+
+        closest_rec
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut objects = self.objects.iter();
+        let mut bbox = objects.next()?.bounding_box()?;
+        for object in objects {
+            bbox = Aabb::surrounding(&bbox, &object.bounding_box()?);
+        }
+        Some(bbox)
+    }
+    fn can_sample(&self) -> bool {
+        self.objects.iter().any(|object| object.can_sample())
+    }
+
+    // Picks one of the objects that can be sampled at random, so the density
+    // is their average. The rest are skipped.
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let (sum, count) = self
+            .objects
+            .iter()
+            .filter(|object| object.can_sample())
+            .fold((0.0, 0), |(sum, count), object| {
+                (sum + object.pdf_value(origin, direction), count + 1)
+            });
+        if count == 0 {
+            return 0.0;
+        }
+        sum / count as f64
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut dyn RngCore) -> Vec3 {
+        let mut samplable = self.objects.iter().filter(|object| object.can_sample());
+        let count = samplable.clone().count();
+        if count == 0 {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+
+        let index = rng.gen_range(0..count);
+        samplable.nth(index).unwrap().random(origin, rng)
+    }
+
+    fn box_hits(&self, r: &Ray, t_min: f64, t_max: f64) -> u32 {
+        self.objects
+            .iter()
+            .map(|object| object.box_hits(r, t_min, t_max))
+            .sum()
+    }
+}
+
+pub enum ScatterKind {
+    // One outgoing ray (mirrors, glass), followed as is
+    Specular(Ray),
+    // Directions to draw from a density, which ray_color may mix with light
+    // samples and weights by scattering_pdf() over the density used
+    Scattering(Arc<dyn Pdf>),
+}
+
+pub struct ScatterRecord {
+    pub attenuation: Color,
+    pub kind: ScatterKind,
+}
+
+impl ScatterRecord {
+    pub fn specular(attenuation: Color, r: Ray) -> ScatterRecord {
+        ScatterRecord {
+            attenuation,
+            kind: ScatterKind::Specular(r),
+        }
+    }
+
+    pub fn scattering(attenuation: Color, pdf: Arc<dyn Pdf>) -> ScatterRecord {
+        ScatterRecord {
+            attenuation,
+            kind: ScatterKind::Scattering(pdf),
+        }
+    }
+}
+
+pub trait Material: Send + Sync {
+    // None if the ray is absorbed
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord>;
+
+    // Light given off at the hit towards where `r_in` came from. The
+    // record's front_face says which side was hit.
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // How strongly light scattered towards `scattered` is kept (the BRDF times
+    // the cosine), for materials that return ScatterKind::Scattering
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        0.0
+    }
+}
+
+pub struct Lambertian {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(albedo: Color) -> Lambertian {
+        Lambertian::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>) -> Lambertian {
+        Lambertian { albedo }
+    }
+}
+
+impl Material for Lambertian {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        Some(ScatterRecord::scattering(
+            attenuation,
+            Arc::new(CosinePdf::new(&rec.normal)),
+        ))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        CosinePdf::new(&rec.normal).value(&scattered.direction())
+    }
+}
+
+pub struct Metal {
+    albedo: Color,
+    // Fuzz along the hit's tangent and bitangent, see
+    // Onb::from_w_and_tangent
+    roughness_u: f64,
+    roughness_v: f64,
+}
+
+impl Metal {
+    pub fn new(albedo: Color, roughness: f64) -> Metal {
+        Metal::new_anisotropic(albedo, roughness, roughness)
+    }
+
+    // Brushed metal: streaks run along the axis with the larger roughness
+    pub fn new_anisotropic(albedo: Color, roughness_u: f64, roughness_v: f64) -> Metal {
+        Metal {
+            albedo,
+            roughness_u: roughness_u.clamp(0.0, 1.0),
+            roughness_v: roughness_v.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Material for Metal {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let reflected_dir = Vec3::reflect(&r.unit_direction(), &rec.normal);
+
+        let fuzz = Vec3::rand_unit_vector(rng);
+        let fuzz = if self.roughness_u == self.roughness_v {
+            self.roughness_u * fuzz
+        } else {
+            // Stretch the fuzz sphere into an ellipsoid along the tangents
+            let frame = Onb::from_w_and_tangent(&rec.normal, rec.tangent);
+            frame.local(&Vec3::new(
+                self.roughness_u * Vec3::dot(&fuzz, &frame.u()),
+                self.roughness_v * Vec3::dot(&fuzz, &frame.v()),
+                0.5 * (self.roughness_u + self.roughness_v) * Vec3::dot(&fuzz, &frame.w()),
+            ))
+        };
+
+        let r_scattered = Ray::new_timed(rec.p, reflected_dir + fuzz, r.time());
+        if Vec3::dot(&r_scattered.direction(), &rec.normal) > 0.0 {
+            Some(ScatterRecord::specular(self.albedo, r_scattered))
+        } else {
+            None
+        }
+    }
+}
+
+// Physically based metal: microfacets with the GGX (Trowbridge-Reitz)
+// distribution of normals. A facet normal is sampled from it and the ray
+// mirrored about that, then weighted by Schlick's Fresnel term, with the
+// albedo as the color at normal incidence, and Smith's shadowing term.
+pub struct GgxMetal {
+    pub albedo: Color,
+    // Perceptual roughness in [0, 1], squared for the distribution's alpha.
+    // 0 is a perfect mirror.
+    pub roughness: f64,
+}
+
+impl GgxMetal {
+    pub fn new(albedo: Color, roughness: f64) -> GgxMetal {
+        GgxMetal {
+            albedo,
+            roughness: roughness.clamp(0.0, 1.0),
+        }
+    }
+
+    // Smith's masking for one direction at cosine `cosine` to the normal
+    fn g1(cosine: f64, alpha: f64) -> f64 {
+        let alpha2 = alpha * alpha;
+        2.0 * cosine / (cosine + (alpha2 + (1.0 - alpha2) * cosine * cosine).sqrt())
+    }
+}
+
+impl Material for GgxMetal {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let alpha = self.roughness * self.roughness;
+        let view = -r.unit_direction();
+
+        // Facet normal with density D(h) * cos(theta_h)
+        let (r1, r2): (f64, f64) = (rng.gen(), rng.gen());
+        let tan2_theta = alpha * alpha * r1 / (1.0 - r1);
+        let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * r2;
+        let h = Onb::from_w(&rec.normal).local(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+
+        let reflected = r.unit_direction().reflect(&h);
+        let n_dot_v = Vec3::dot(&rec.normal, &view);
+        let n_dot_l = Vec3::dot(&rec.normal, &reflected);
+        let v_dot_h = Vec3::dot(&view, &h);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 || v_dot_h <= 0.0 {
+            return None;
+        }
+
+        // The BRDF D G F / (4 (n.v) (n.l)) times n.l, over the density of
+        // `reflected`, D (n.h) / (4 (v.h)). D cancels out.
+        let fresnel =
+            self.albedo + (Color::new(1.0, 1.0, 1.0) - self.albedo) * (1.0 - v_dot_h).powi(5);
+        let g = GgxMetal::g1(n_dot_v, alpha) * GgxMetal::g1(n_dot_l, alpha);
+        let attenuation = fresnel * g * v_dot_h / (n_dot_v * cos_theta);
+
+        let r_scattered = Ray::new_timed(rec.p, reflected, r.time());
+        Some(ScatterRecord::specular(attenuation, r_scattered))
+    }
+}
+
+pub struct Dielectric {
+    pub ior: f64,
+    // Constant tint applied on every bounce, white for clear glass
+    pub attenuation: Color,
+    // Beer–Lambert coefficient per unit of distance travelled inside
+    pub absorption: Color,
+    // Separate red, green and blue indices for dispersion, used instead of
+    // `ior`
+    pub dispersion: Option<[f64; 3]>,
+}
+
+impl Dielectric {
+    pub fn new(ior: f64) -> Self {
+        Dielectric::new_tinted(ior, Color::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn new_tinted(ior: f64, attenuation: Color) -> Self {
+        Dielectric {
+            ior,
+            attenuation,
+            absorption: Color::new(0.0, 0.0, 0.0),
+            dispersion: None,
+        }
+    }
+
+    pub fn new_absorbing(ior: f64, absorption: Color) -> Self {
+        Dielectric {
+            ior,
+            attenuation: Color::new(1.0, 1.0, 1.0),
+            absorption,
+            dispersion: None,
+        }
+    }
+
+    // Glass that bends each color by its own index (blue usually the most),
+    // splitting white light into a spectrum
+    pub fn new_dispersive(ior_r: f64, ior_g: f64, ior_b: f64) -> Self {
+        Dielectric {
+            dispersion: Some([ior_r, ior_g, ior_b]),
+            ..Dielectric::new(ior_g)
+        }
+    }
+
+    pub fn reflectance(cosine: f64, ior: f64) -> f64 {
+        // Schlick's approximation
+        let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+        r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+    }
+}
+
+impl Material for Dielectric {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        // A dispersive ray carries on as one randomly picked color, tripled
+        // to make up for the other two. Paths already narrowed to a color by
+        // earlier glass keep it.
+        let (ior, channel, weight) = match (self.dispersion, r.channel()) {
+            (Some(iors), Some(channel)) => {
+                (iors[channel], Some(channel), Color::new(1.0, 1.0, 1.0))
+            }
+            (Some(iors), None) => {
+                let channel = rng.gen_range(0..3);
+                let mut mask = [0.0; 3];
+                mask[channel] = 3.0;
+                (iors[channel], Some(channel), Color::from(mask))
+            }
+            (None, channel) => (self.ior, channel, Color::new(1.0, 1.0, 1.0)),
+        };
+        let refraction_ratio = if rec.front_face { 1.0 / ior } else { ior };
+
+        let unit_direction = r.unit_direction();
+        let cos_theta = Vec3::dot(&(-unit_direction), &rec.normal).min(1.0);
+        let sin_theta = f64::sqrt(1.0 - cos_theta.powi(2));
+
+        let total_internal_reflection = refraction_ratio * sin_theta > 1.0;
+
+        let r_direction = if total_internal_reflection
+            || Dielectric::reflectance(cos_theta, refraction_ratio) > rng.gen::<f64>()
+        {
+            unit_direction.reflect(&rec.normal)
+        } else {
+            unit_direction.refract(&rec.normal, refraction_ratio)
+        };
+
+        let r_scattered = Ray::new_timed(rec.p, r_direction, r.time()).with_channel(channel);
+
+        // Hitting the surface from inside means the ray has just crossed the
+        // medium, starting from the previous hit where it was spawned
+        let attenuation = if rec.front_face {
+            self.attenuation
+        } else {
+            let distance = rec.t * r.direction().length();
+            self.attenuation * (-distance * self.absorption).map(f64::exp)
+        };
+
+        Some(ScatterRecord::specular(attenuation * weight, r_scattered))
+    }
+}
+
+// A sheet of glass too thin to offset the rays through it, for soap bubbles
+// and window panes modelled as a single surface. Rays reflect by Fresnel
+// and otherwise pass straight through.
+pub struct ThinDielectric {
+    pub ior: f64,
+    // Tint of transmitted light, white for clear glass
+    pub attenuation: Color,
+}
+
+impl ThinDielectric {
+    pub fn new(ior: f64) -> ThinDielectric {
+        ThinDielectric::new_tinted(ior, Color::new(1.0, 1.0, 1.0))
+    }
+
+    pub fn new_tinted(ior: f64, attenuation: Color) -> ThinDielectric {
+        ThinDielectric { ior, attenuation }
+    }
+}
+
+impl Material for ThinDielectric {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let unit_direction = r.unit_direction();
+        let cos_theta = Vec3::dot(&(-unit_direction), &rec.normal).min(1.0);
+
+        // Both faces reflect, and light bouncing between them adds up to
+        // 2R / (1 + R). There's no total internal reflection since rays
+        // leave at the angle they came in.
+        let reflectance = Dielectric::reflectance(cos_theta, 1.0 / self.ior);
+        let reflectance = 2.0 * reflectance / (1.0 + reflectance);
+
+        let (attenuation, r_direction) = if reflectance > rng.gen::<f64>() {
+            (
+                Color::new(1.0, 1.0, 1.0),
+                unit_direction.reflect(&rec.normal),
+            )
+        } else {
+            (self.attenuation, r.direction())
+        };
+
+        let r_scattered = Ray::new_timed(rec.p, r_direction, r.time());
+        Some(ScatterRecord::specular(attenuation, r_scattered))
+    }
+}
+
+// Diffuse base under a clear dielectric coat: Schlick's Fresnel term picks
+// between a mirror reflection off the coat and a Lambertian bounce off the
+// base, so grazing angles look glossy and head-on shows the base color
+pub struct Coated {
+    pub base: Color,
+    pub ior: f64,
+}
+
+impl Coated {
+    pub fn new(base: Color, ior: f64) -> Coated {
+        Coated { base, ior }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let unit_direction = r.unit_direction();
+        let cos_theta = Vec3::dot(&(-unit_direction), &rec.normal).clamp(0.0, 1.0);
+
+        if Dielectric::reflectance(cos_theta, self.ior) > rng.gen::<f64>() {
+            let reflected = unit_direction.reflect(&rec.normal);
+            let r_scattered = Ray::new_timed(rec.p, reflected, r.time());
+            return Some(ScatterRecord::specular(
+                Color::new(1.0, 1.0, 1.0),
+                r_scattered,
+            ));
+        }
+
+        Some(ScatterRecord::scattering(
+            self.base,
+            Arc::new(CosinePdf::new(&rec.normal)),
+        ))
+    }
+
+    // The base's Lambertian lobe, the coat reflection is specular
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        CosinePdf::new(&rec.normal).value(&scattered.direction())
+    }
+}
+
+// Wraps a material with a tangent-space normal map: the texture's color
+// (0.5, 0.5, 1.0) is the unperturbed normal, red tilts towards the tangent
+// and green towards the bitangent
+pub struct NormalMapped {
+    pub inner: Arc<dyn Material>,
+    pub normal_map: Arc<dyn Texture>,
+}
+
+impl NormalMapped {
+    pub fn new(inner: Arc<dyn Material>, normal_map: Arc<dyn Texture>) -> NormalMapped {
+        NormalMapped { inner, normal_map }
+    }
+
+    // A copy of `rec` with the mapped normal. Maps that would tip it below
+    // the surface keep the geometric normal.
+    pub fn perturbed(&self, rec: &HitRecord) -> HitRecord {
+        let n = rec.normal;
+        let frame = Onb::from_w_and_tangent(&n, rec.tangent);
+
+        let m = 2.0 * self.normal_map.value(rec.u, rec.v, &rec.p) - Vec3::new(1.0, 1.0, 1.0);
+        let mapped = frame.local(&m);
+        let normal = if Vec3::dot(&mapped, &n) > 0.0 {
+            mapped.as_unit_vector()
+        } else {
+            n
+        };
+
+        HitRecord {
+            t: rec.t,
+            p: rec.p,
+            u: rec.u,
+            v: rec.v,
+            normal,
+            mat: rec.mat.clone(),
+            front_face: rec.front_face,
+            tangent: rec.tangent,
+        }
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        self.inner.scatter(r, &self.perturbed(rec), rng)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.inner
+            .scattering_pdf(r_in, &self.perturbed(rec), scattered)
+    }
+}
+
+pub struct DiffuseLight {
+    emit: Arc<dyn Texture>,
+    // Scales the texture, so it can stay in [0, 1] for bright lights
+    pub intensity: f64,
+    // One-sided lights are dark from behind, the side the normal points away
+    // from
+    pub two_sided: bool,
+}
+
+// Lights are two-sided unless two_sided is cleared
+impl DiffuseLight {
+    pub fn new(emit: Arc<dyn Texture>, intensity: f64) -> DiffuseLight {
+        DiffuseLight {
+            emit,
+            intensity,
+            two_sided: true,
+        }
+    }
+
+    pub fn from_color(emit: Color) -> DiffuseLight {
+        DiffuseLight::new(Arc::new(SolidColor::new(emit)), 1.0)
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        if !self.two_sided && !rec.front_face {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.intensity * self.emit.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+// Shines in a cone around `direction`: at full strength within `inner`
+// degrees of it, fading smoothly to nothing at `outer`. Put it on a small
+// object, the cone is measured at each point of its surface.
+pub struct SpotLight {
+    emit: Arc<dyn Texture>,
+    pub intensity: f64,
+    direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    pub fn new(emit: Color, direction: Vec3, inner: f64, outer: f64, intensity: f64) -> SpotLight {
+        SpotLight::from_texture(
+            Arc::new(SolidColor::new(emit)),
+            direction,
+            inner,
+            outer,
+            intensity,
+        )
+    }
+
+    pub fn from_texture(
+        emit: Arc<dyn Texture>,
+        direction: Vec3,
+        inner: f64,
+        outer: f64,
+        intensity: f64,
+    ) -> SpotLight {
+        let outer = outer.max(inner);
+        SpotLight {
+            emit,
+            intensity,
+            direction: direction.as_unit_vector(),
+            cos_inner: inner.to_radians().cos(),
+            cos_outer: outer.to_radians().cos(),
+        }
+    }
+
+    // 1 inside the inner cone, 0 outside the outer one, smoothstep between
+    pub fn falloff(&self, towards: &Vec3) -> f64 {
+        let cosine = Vec3::dot(&towards.as_unit_vector(), &self.direction);
+        if cosine >= self.cos_inner {
+            return 1.0;
+        }
+        if cosine <= self.cos_outer {
+            return 0.0;
+        }
+        let t = (cosine - self.cos_outer) / (self.cos_inner - self.cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Material for SpotLight {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        let falloff = self.falloff(&-r_in.direction());
+        if falloff == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        falloff * self.intensity * self.emit.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Isotropic {
+        Isotropic::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>) -> Isotropic {
+        Isotropic { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, r: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<ScatterRecord> {
+        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        Some(ScatterRecord::scattering(attenuation, Arc::new(SpherePdf)))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+// The world is shared between render threads, so everything in it must stay Send + Sync
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync + ?Sized>() {}
+
+    assert_send_sync::<HittableList>();
+    assert_send_sync::<Sphere>();
+    assert_send_sync::<MovingSphere>();
+    assert_send_sync::<XyRect>();
+    assert_send_sync::<XzRect>();
+    assert_send_sync::<YzRect>();
+    assert_send_sync::<BoxObj>();
+    assert_send_sync::<Triangle>();
+    assert_send_sync::<Quad>();
+    assert_send_sync::<Plane>();
+    assert_send_sync::<ConstantMedium>();
+    assert_send_sync::<Lambertian>();
+    assert_send_sync::<Metal>();
+    assert_send_sync::<GgxMetal>();
+    assert_send_sync::<Dielectric>();
+    assert_send_sync::<ThinDielectric>();
+    assert_send_sync::<Coated>();
+    assert_send_sync::<NormalMapped>();
+    assert_send_sync::<DiffuseLight>();
+    assert_send_sync::<SpotLight>();
+    assert_send_sync::<Isotropic>();
+    assert_send_sync::<HitRecord>();
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::assert_vec3_eq;
+
+    fn gray() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+    }
+
+    fn down_z() -> Ray {
+        Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn world_can_be_handed_to_another_thread() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            gray(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.0)),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        let world = Arc::new(world);
+
+        let shared = world.clone();
+        let t = std::thread::spawn(move || {
+            shared.hit(&down_z(), 0.001, f64::INFINITY).map(|rec| rec.t)
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(t, Some(0.5));
+        assert_eq!(
+            t,
+            world.hit(&down_z(), 0.001, f64::INFINITY).map(|rec| rec.t)
+        );
+    }
+
+    #[test]
+    fn sphere_box_spans_center_plus_minus_radius() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 0.5, gray());
+        let bbox = sphere.bounding_box().unwrap();
+        assert_eq!(bbox.min, Point3::new(0.5, 1.5, 2.5));
+        assert_eq!(bbox.max, Point3::new(1.5, 2.5, 3.5));
+    }
+
+    #[test]
+    fn list_box_encloses_every_object() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-2.0, 0.0, 0.0),
+            1.0,
+            gray(),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(3.0, 1.0, -1.0),
+            0.5,
+            gray(),
+        )));
+
+        let bbox = world.bounding_box().unwrap();
+        assert_eq!(bbox.min, Point3::new(-3.0, -1.0, -1.5));
+        assert_eq!(bbox.max, Point3::new(3.5, 1.5, 1.0));
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_the_ray_time() {
+        let sphere = MovingSphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            Point3::new(0.3, 0.0, -2.0),
+            0.0,
+            1.0,
+            0.5,
+            gray(),
+        );
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let direction = Vec3::new(0.0, 0.0, -1.0);
+
+        let at_start = sphere
+            .hit(
+                &Ray::new_timed(origin, direction, 0.0),
+                0.001,
+                f64::INFINITY,
+            )
+            .unwrap();
+        assert!((at_start.p - Point3::new(0.0, 0.0, -1.5)).length() < 1e-9);
+
+        // By time 1 the center has moved off the ray, so it meets the
+        // surface further away
+        let at_end = sphere
+            .hit(
+                &Ray::new_timed(origin, direction, 1.0),
+                0.001,
+                f64::INFINITY,
+            )
+            .unwrap();
+        assert!((at_end.p - Point3::new(0.0, 0.0, -1.6)).length() < 1e-9);
+    }
+
+    #[test]
+    fn sphere_uv_puts_north_pole_at_v_1_and_x_axis_at_u_half() {
+        let (_, v) = sphere_uv(&Point3::new(0.0, 1.0, 0.0));
+        assert!((v - 1.0).abs() < 1e-9);
+        let (u, _) = sphere_uv(&Point3::new(1.0, 0.0, 0.0));
+        assert!((u - 0.5).abs() < 1e-9);
+
+        // The same through a hit on the top of a unit sphere
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, gray());
+        let r = Ray::new(Point3::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.v - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ray_down_z_hits_xy_rect_at_its_plane() {
+        let rect = XyRect::new(-1.0, 1.0, -1.0, 1.0, -3.0, gray());
+        let rec = rect.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert_eq!(rec.p, Point3::new(0.0, 0.0, -3.0));
+
+        let beside = Ray::new(Point3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(rect.hit(&beside, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn ray_at_box_center_hits_a_face_facing_back() {
+        let b = BoxObj::new(
+            Point3::new(-1.0, -1.0, -4.0),
+            Point3::new(1.0, 1.0, -2.0),
+            gray(),
+        );
+        for origin in [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(3.0, 0.5, -3.0),
+            Point3::new(-0.2, -5.0, -3.1),
+        ] {
+            let r = Ray::new(origin, Point3::new(0.0, 0.0, -3.0) - origin);
+            let rec = b.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!(rec.front_face);
+            assert!(Vec3::dot(&rec.normal, &r.direction()) < 0.0);
+            // On the surface of the box
+            let on_face = (0..3)
+                .any(|a| (rec.p[a] - b.min[a]).abs() < 1e-9 || (rec.p[a] - b.max[a]).abs() < 1e-9);
+            assert!(on_face, "{:?}", rec.p);
+        }
+    }
+
+    // Fraction of rays straight through a unit box of medium that scatter
+    // inside it
+    fn medium_scatter_fraction(density: f64) -> f64 {
+        let boundary = Arc::new(BoxObj::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            gray(),
+        ));
+        let medium = ConstantMedium::new(boundary, density, Color::new(1.0, 1.0, 1.0));
+
+        let rays = 1000;
+        let scattered = (0..rays)
+            .filter(|&i| {
+                let x = (i as f64 + 0.5) / rays as f64;
+                let r = Ray::new(Point3::new(x, 0.5, 3.0), Vec3::new(0.0, 0.0, -1.0));
+                medium.hit(&r, 0.001, f64::INFINITY).is_some()
+            })
+            .count();
+        scattered as f64 / rays as f64
+    }
+
+    #[test]
+    fn dense_media_scatter_and_thin_ones_let_rays_through() {
+        // 1 - exp(-density) of the rays scatter over a unit distance
+        let dense = medium_scatter_fraction(10.0);
+        assert!(dense > 0.99, "{}", dense);
+        let thin = medium_scatter_fraction(0.01);
+        assert!(thin < 0.05, "{}", thin);
+    }
+
+    #[test]
+    fn ray_through_triangle_centroid_has_equal_barycentrics() {
+        let triangle = Triangle::new(
+            Point3::new(-1.0, -1.0, -2.0),
+            Point3::new(1.0, -1.0, -2.0),
+            Point3::new(0.0, 1.0, -2.0),
+            gray(),
+        );
+        let centroid = (triangle.v0 + triangle.v1 + triangle.v2) / 3.0;
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), centroid);
+
+        let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 1.0 / 3.0).abs() < 1e-9);
+        assert!((rec.v - 1.0 / 3.0).abs() < 1e-9);
+        assert!((rec.p - centroid).length() < 1e-9);
+    }
+
+    #[test]
+    fn ray_down_hits_a_ground_plane_and_ray_up_misses() {
+        let plane = Plane::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0), gray());
+        let down = Ray::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.0, -1.0, 0.0));
+        let rec = plane.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.p, Point3::new(1.0, 0.0, 3.0));
+        assert_eq!(rec.t, 2.0);
+
+        let up = Ray::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 1.0, 0.0));
+        assert!(plane.hit(&up, 0.001, f64::INFINITY).is_none());
+        assert!(plane.bounding_box().is_none());
+    }
+
+    #[test]
+    fn nearer_of_two_overlapping_objects_wins() {
+        let near = Arc::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, gray()));
+        let far = Arc::new(Sphere::new(Point3::new(0.0, 0.0, -2.5), 0.5, gray()));
+        for objects in [
+            vec![near.clone() as Arc<dyn Hittable>, far.clone()],
+            vec![far.clone() as Arc<dyn Hittable>, near.clone()],
+        ] {
+            let mut world = HittableList::new();
+            for object in objects {
+                world.add(object);
+            }
+            let rec = world.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+            assert!((rec.t - 1.5).abs() < 1e-12, "t = {}", rec.t);
+
+            // Past both near surfaces, the near sphere's far side at 2.5
+            // comes before the far sphere's at 3
+            let rec = world.hit(&down_z(), 2.2, f64::INFINITY).unwrap();
+            assert!((rec.t - 2.5).abs() < 1e-12, "t = {}", rec.t);
+        }
+    }
+
+    #[test]
+    fn t_min_past_the_nearest_surface_returns_the_farther_one() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(XyRect::new(-1.0, 1.0, -1.0, 1.0, -1.0, gray())));
+        world.add(Arc::new(XyRect::new(-1.0, 1.0, -1.0, 1.0, -3.0, gray())));
+        let rec = world.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 1.0);
+        let rec = world.hit(&down_z(), 1.5, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 3.0);
+        assert!(world.hit(&down_z(), 1.5, 2.5).is_none());
+    }
+
+    #[test]
+    fn tinted_glass_tints_and_clear_glass_does_not() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let green = Color::new(0.2, 0.9, 0.2);
+        for (glass, expected) in [
+            (Dielectric::new_tinted(1.5, green), green),
+            (Dielectric::new(1.5), Color::new(1.0, 1.0, 1.0)),
+        ] {
+            let sphere = Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, Arc::new(glass));
+            let rec = sphere.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+            let scatter = rec.mat.scatter(&down_z(), &rec, &mut rng).unwrap();
+            assert_eq!(scatter.attenuation, expected);
+        }
+    }
+
+    // Attenuation of a ray crossing a glass sphere of `radius` along its
+    // diameter, red being absorbed
+    fn attenuation_through_glass(radius: f64) -> Color {
+        let glass = Dielectric {
+            absorption: Color::new(1.0, 0.0, 0.0),
+            ..Dielectric::new(1.5)
+        };
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), radius, Arc::new(glass));
+        let inside = Ray::new(Point3::new(0.0, 0.0, radius), Vec3::new(0.0, 0.0, -1.0));
+        let rec = sphere.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert!(!rec.front_face);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        rec.mat
+            .scatter(&inside, &rec, &mut rng)
+            .unwrap()
+            .attenuation
+    }
+
+    #[test]
+    fn thicker_glass_absorbs_more() {
+        let thin = attenuation_through_glass(0.1);
+        let thick = attenuation_through_glass(1.0);
+        assert!((thin.x() - (-0.2f64).exp()).abs() < 1e-9);
+        assert!((thick.x() - (-2.0f64).exp()).abs() < 1e-9);
+        assert_eq!(thick.y(), 1.0);
+    }
+
+    #[test]
+    fn brushed_metal_streaks_along_the_hit_tangent() {
+        // On the +x side of a sphere the tangent runs along -z, while
+        // Onb::from_w(+x) would have put u along y
+        let brushed = Metal::new_anisotropic(Color::new(0.9, 0.9, 0.9), 0.5, 0.0);
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, Arc::new(brushed));
+        let r = Ray::new(Point3::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_vec3_eq!(rec.tangent.unwrap(), Vec3::new(0.0, 0.0, -1.0), 1e-12);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let (mut spread_y, mut spread_z) = (0.0, 0.0);
+        for _ in 0..1000 {
+            if let Some(ScatterRecord {
+                kind: ScatterKind::Specular(scattered),
+                ..
+            }) = rec.mat.scatter(&r, &rec, &mut rng)
+            {
+                let d = scattered.unit_direction();
+                spread_y += d.y().abs();
+                spread_z += d.z().abs();
+            }
+        }
+        assert!(spread_z > 100.0, "{}", spread_z);
+        assert!(spread_y < 1e-9, "{}", spread_y);
+    }
+
+    // Share of scatters off a coated floor taking the specular branch, for a
+    // ray coming down at `direction`
+    fn coat_reflection_share(direction: Vec3) -> f64 {
+        let floor = Plane::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Arc::new(Coated::new(Color::new(0.5, 0.5, 0.5), 1.5)),
+        );
+        let r = Ray::new(Point3::new(0.0, 1.0, 0.0), direction);
+        let rec = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let specular = (0..1000)
+            .filter(|_| {
+                matches!(
+                    rec.mat.scatter(&r, &rec, &mut rng).unwrap().kind,
+                    ScatterKind::Specular(_)
+                )
+            })
+            .count();
+        specular as f64 / 1000.0
+    }
+
+    #[test]
+    fn coat_reflects_mostly_at_grazing_angles() {
+        let grazing = coat_reflection_share(Vec3::new(1.0, -0.02, 0.0));
+        let head_on = coat_reflection_share(Vec3::new(0.0, -1.0, 0.0));
+        assert!(grazing > 0.8, "{}", grazing);
+        assert!(head_on < 0.1, "{}", head_on);
+    }
+
+    #[test]
+    fn ray_from_the_center_exits_at_the_radius() {
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.5, gray());
+        let r = Ray::new(Point3::new(1.0, 2.0, 3.0), Vec3::new(0.0, 0.6, -0.8));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-12);
+        assert!(!rec.front_face);
+
+        // The hollow-glass trick: a negative radius turns the sphere inside out
+        let hollow = Sphere::new(Point3::new(1.0, 2.0, 3.0), -2.5, gray());
+        let rec = hollow.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 2.5).abs() < 1e-12);
+        assert!(rec.front_face);
+    }
+
+    #[test]
+    fn tilted_quad_is_hit_inside_and_missed_just_outside() {
+        let q = Point3::new(-1.0, -1.0, -3.0);
+        let u = Vec3::new(2.0, 0.0, 1.0);
+        let v = Vec3::new(0.0, 2.0, 0.0);
+        let quad = Quad::new(q, u, v, gray());
+        let toward =
+            |alpha: f64, beta: f64| Ray::new(Point3::new(0.0, 0.0, 0.0), q + alpha * u + beta * v);
+
+        let rec = quad.hit(&toward(0.25, 0.5), 0.001, f64::INFINITY).unwrap();
+        assert_vec3_eq!(rec.p, q + 0.25 * u + 0.5 * v, 1e-9);
+        assert!((rec.u - 0.25).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+
+        assert!(quad.hit(&toward(1.01, 0.5), 0.001, f64::INFINITY).is_none());
+        assert!(quad
+            .hit(&toward(0.5, -0.01), 0.001, f64::INFINITY)
+            .is_none());
+    }
+
+    #[test]
+    fn collected_list_hits_like_one_built_with_add() {
+        let spheres: Vec<Arc<dyn Hittable>> = (0..3)
+            .map(|i| {
+                let center = Point3::new(i as f64 - 1.0, 0.0, -2.0 - i as f64);
+                Arc::new(Sphere::new(center, 0.4, gray())) as Arc<dyn Hittable>
+            })
+            .collect();
+        let collected: HittableList = spheres.iter().cloned().collect();
+        let mut added = HittableList::new();
+        for sphere in &spheres {
+            added.add(sphere.clone());
+        }
+
+        let mut rng = SmallRng::seed_from_u64(8);
+        for _ in 0..500 {
+            let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::rand_unit_vector(&mut rng));
+            let hit_collected = collected.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            let hit_added = added.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(hit_collected, hit_added);
+        }
+        assert!(collected.hit(&down_z(), 0.001, f64::INFINITY).is_some());
+    }
+
+    // The textbook roots, before the rewrite that avoids cancellation
+    fn reference_sphere_t(center: Point3, radius: f64, r: &Ray, t_min: f64) -> Option<f64> {
+        let oc = r.origin() - center;
+        let a = r.direction().length_squared();
+        let hb = Vec3::dot(&oc, &r.direction());
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = hb * hb - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        [
+            (-hb - discriminant.sqrt()) / a,
+            (-hb + discriminant.sqrt()) / a,
+        ]
+        .into_iter()
+        .find(|t| *t >= t_min)
+    }
+
+    #[test]
+    fn sphere_hits_match_the_textbook_roots_for_any_direction_length() {
+        let center = Point3::new(0.5, -0.25, -3.0);
+        let sphere = Sphere::new(center, 1.2, gray());
+        let mut rng = SmallRng::seed_from_u64(9);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = Vec3::rand(&mut rng, -3.0, 3.0);
+            let target = center + Vec3::rand(&mut rng, -1.5, 1.5);
+            // Unnormalized directions, as transforms and motion produce
+            let direction = (target - origin) * rng.gen_range(0.1..10.0);
+            let r = Ray::new(origin, direction);
+
+            let got = sphere.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            let expected = reference_sphere_t(center, 1.2, &r, 0.001);
+            match (got, expected) {
+                (Some(got), Some(expected)) => {
+                    assert!((got - expected).abs() <= 1e-9 * expected.abs().max(1.0));
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("{:?} vs {:?} for {:?}", got, expected, r),
+            }
+        }
+        assert!(hits > 1000, "{}", hits);
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal_and_a_tilted_one_leans_along_u() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -3.0), 1.0, gray());
+        let rec = sphere.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+        let mapped =
+            |color| NormalMapped::new(gray(), Arc::new(SolidColor::new(color))).perturbed(&rec);
+
+        let flat = mapped(Color::new(0.5, 0.5, 1.0));
+        assert_vec3_eq!(flat.normal, rec.normal, 1e-12);
+
+        // (1, 0, 1) in tangent space: halfway between the normal and tangent
+        let tilted = mapped(Color::new(1.0, 0.5, 1.0));
+        let tangent = rec.tangent.unwrap().as_unit_vector();
+        let expected = (rec.normal + tangent).as_unit_vector();
+        assert_vec3_eq!(tilted.normal, expected, 1e-12);
+    }
+
+    #[test]
+    fn metal_scatters_specularly_and_lambertian_by_a_pdf() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -3.0), 1.0, gray());
+        let rec = sphere.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+        let mut rng = SmallRng::seed_from_u64(13);
+
+        let metal = Metal::new(Color::new(0.9, 0.9, 0.9), 0.0);
+        let srec = metal.scatter(&down_z(), &rec, &mut rng).unwrap();
+        match srec.kind {
+            ScatterKind::Specular(r) => {
+                assert_vec3_eq!(r.direction(), Vec3::new(0.0, 0.0, 1.0), 1e-12)
+            }
+            ScatterKind::Scattering(_) => panic!("metal scattered by a pdf"),
+        }
+
+        let srec = gray().scatter(&down_z(), &rec, &mut rng).unwrap();
+        assert!(matches!(srec.kind, ScatterKind::Scattering(_)));
+    }
+
+    #[test]
+    fn removing_shifts_indices_and_stops_hits() {
+        let near: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::new(0.0, 0.0, -2.0), 0.5, gray()));
+        let far: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::new(0.0, 0.0, -5.0), 0.5, gray()));
+        let beside: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::new(3.0, 0.0, -2.0), 0.5, gray()));
+        let mut world = HittableList::new();
+        assert!(world.is_empty());
+        for object in [&near, &far, &beside] {
+            world.add(object.clone());
+        }
+        assert_eq!(world.len(), 3);
+        assert_eq!(world.iter().count(), 3);
+
+        let removed = world.remove(0);
+        assert!(Arc::ptr_eq(&removed, &near));
+        assert_eq!(world.len(), 2);
+        assert!(Arc::ptr_eq(world.get(0).unwrap(), &far));
+        assert!(Arc::ptr_eq(world.get(1).unwrap(), &beside));
+        assert!(world.get(2).is_none());
+
+        // The ray now reaches the far sphere
+        let rec = world.hit(&down_z(), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-12, "{}", rec.t);
+        world.remove(0);
+        assert!(world.hit(&down_z(), 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn thin_glass_passes_most_light_straight_through_head_on() {
+        let pane = XyRect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            -3.0,
+            Arc::new(ThinDielectric::new(1.5)),
+        );
+        let r = down_z();
+        let rec = pane.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let mut rng = SmallRng::seed_from_u64(17);
+
+        let n = 2000;
+        let mut transmitted = 0;
+        for _ in 0..n {
+            let srec = rec.mat.scatter(&r, &rec, &mut rng).unwrap();
+            let ScatterKind::Specular(scattered) = srec.kind else {
+                panic!("thin glass scattered by a pdf");
+            };
+            if scattered.direction() == r.direction() {
+                transmitted += 1;
+            } else {
+                assert_vec3_eq!(scattered.direction(), Vec3::new(0.0, 0.0, 1.0), 1e-12);
+            }
+        }
+        // Both faces reflect 4% head on, 2R / (1 + R) ≈ 7.7% in all
+        let share = transmitted as f64 / n as f64;
+        assert!((share - (1.0 - 0.08 / 1.04)).abs() < 0.02, "{}", share);
+    }
+
+    #[test]
+    fn one_sided_light_is_dark_from_behind() {
+        let texture: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::new(1.0, 0.5, 0.25)));
+        let emitted_toward = |light: DiffuseLight, r: &Ray| {
+            let rect = XyRect::new(-1.0, 1.0, -1.0, 1.0, -3.0, Arc::new(light));
+            let rec = rect.hit(r, 0.001, f64::INFINITY).unwrap();
+            rec.mat.emitted(r, &rec)
+        };
+        // The rect's normal is +z, so the front faces the origin
+        let front = down_z();
+        let behind = Ray::new(Point3::new(0.0, 0.0, -6.0), Vec3::new(0.0, 0.0, 1.0));
+        let bright = Color::new(4.0, 2.0, 1.0);
+
+        let one_sided = || DiffuseLight {
+            two_sided: false,
+            ..DiffuseLight::new(texture.clone(), 4.0)
+        };
+        assert_eq!(emitted_toward(one_sided(), &front), bright);
+        assert_eq!(
+            emitted_toward(one_sided(), &behind),
+            Color::new(0.0, 0.0, 0.0)
+        );
+
+        let two_sided = || DiffuseLight::new(texture.clone(), 4.0);
+        assert_eq!(emitted_toward(two_sided(), &front), bright);
+        assert_eq!(emitted_toward(two_sided(), &behind), bright);
+    }
+
+    #[test]
+    fn ggx_is_a_mirror_when_smooth_and_widens_with_roughness() {
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.5, 0.0, -1.0));
+        let mirror = r.unit_direction().reflect(&Vec3::new(0.0, 0.0, 1.0));
+        let mut rng = SmallRng::seed_from_u64(18);
+        // Mean angle to the mirror direction over the rays that leave
+        let mut spread = |roughness| {
+            let floor = XyRect::new(
+                -10.0,
+                10.0,
+                -10.0,
+                10.0,
+                -3.0,
+                Arc::new(GgxMetal::new(Color::new(0.9, 0.9, 0.9), roughness)),
+            );
+            let rec = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let angles: Vec<f64> = (0..2000)
+                .filter_map(|_| rec.mat.scatter(&r, &rec, &mut rng))
+                .map(|srec| match srec.kind {
+                    ScatterKind::Specular(scattered) => {
+                        Vec3::dot(&scattered.unit_direction(), &mirror)
+                            .clamp(-1.0, 1.0)
+                            .acos()
+                    }
+                    ScatterKind::Scattering(_) => panic!("GGX scattered by a pdf"),
+                })
+                .collect();
+            angles.iter().sum::<f64>() / angles.len() as f64
+        };
+
+        assert!(spread(0.0) < 1e-6);
+        let (smooth, rough) = (spread(0.2), spread(0.6));
+        assert!(
+            smooth > 1e-3 && rough > 2.0 * smooth,
+            "{} {}",
+            smooth,
+            rough
+        );
+    }
+
+    #[test]
+    fn dispersive_glass_splits_white_light_three_ways() {
+        let prism = Dielectric::new_dispersive(1.50, 1.52, 1.56);
+        let face = XyRect::new(-10.0, 10.0, -10.0, 10.0, -3.0, gray());
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.6, 0.0, -1.0));
+        let rec = face.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let mut rng = SmallRng::seed_from_u64(19);
+
+        // Refracted directions by channel, ignoring the reflections
+        let mut refracted: [Option<Vec3>; 3] = [None; 3];
+        for _ in 0..300 {
+            let srec = prism.scatter(&r, &rec, &mut rng).unwrap();
+            let ScatterKind::Specular(scattered) = srec.kind else {
+                panic!("glass scattered by a pdf");
+            };
+            if scattered.direction().z() > 0.0 {
+                continue;
+            }
+            let channel = scattered.channel().unwrap();
+            let mut expected = [0.0; 3];
+            expected[channel] = 3.0;
+            assert_eq!(srec.attenuation, Color::from(expected));
+            let direction = scattered.unit_direction();
+            assert_vec3_eq!(
+                *refracted[channel].get_or_insert(direction),
+                direction,
+                1e-12
+            );
+        }
+
+        // Bluer light bends further towards the normal
+        let bend = |channel: usize| refracted[channel].unwrap().x();
+        assert!(bend(0) > bend(1) && bend(1) > bend(2), "{:?}", refracted);
+    }
+
+    #[test]
+    fn glass_keeps_the_color_a_path_already_carries() {
+        let prism = Dielectric::new_dispersive(1.50, 1.52, 1.56);
+        let face = XyRect::new(-10.0, 10.0, -10.0, 10.0, -3.0, gray());
+        let r =
+            Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.6, 0.0, -1.0)).with_channel(Some(2));
+        let rec = face.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let blue = r.unit_direction().refract(&rec.normal, 1.0 / 1.56);
+        let mut rng = SmallRng::seed_from_u64(20);
+
+        for _ in 0..100 {
+            let srec = prism.scatter(&r, &rec, &mut rng).unwrap();
+            let ScatterKind::Specular(scattered) = srec.kind else {
+                panic!("glass scattered by a pdf");
+            };
+            assert_eq!(scattered.channel(), Some(2));
+            assert_eq!(srec.attenuation, Color::new(1.0, 1.0, 1.0));
+            if scattered.direction().z() < 0.0 {
+                assert_vec3_eq!(scattered.unit_direction(), blue.as_unit_vector(), 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn spotlight_is_full_in_the_inner_cone_partial_between_and_dark_outside() {
+        let emit = Color::new(1.0, 1.0, 1.0);
+        let spot = Arc::new(SpotLight::new(
+            emit,
+            Vec3::new(0.0, -1.0, 0.0),
+            20.0,
+            40.0,
+            5.0,
+        ));
+        let bulb = Sphere::new(Point3::new(0.0, 0.0, 0.0), 0.1, spot);
+        // Looking back at the bulb from `degrees` off the spot's axis
+        let seen_from = |degrees: f64| {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            let eye = Point3::new(4.0 * sin, -4.0 * cos, 0.0);
+            let r = Ray::new(eye, -eye);
+            let rec = bulb.hit(&r, 0.001, f64::INFINITY).unwrap();
+            rec.mat.emitted(&r, &rec).x()
+        };
+
+        assert_eq!(seen_from(0.0), 5.0);
+        assert_eq!(seen_from(15.0), 5.0);
+        let partial = seen_from(30.0);
+        assert!(partial > 0.0 && partial < 5.0, "{}", partial);
+        assert!(seen_from(25.0) > partial && partial > seen_from(35.0));
+        assert_eq!(seen_from(45.0), 0.0);
+        assert_eq!(seen_from(120.0), 0.0);
+    }
+
+    #[test]
+    fn summary_counts_spheres_and_triangles_and_bounds_them_all() {
+        let mut world = HittableList::new();
+        for x in [-2.0, 0.0, 2.0] {
+            world.add(Arc::new(Sphere::new(Point3::new(x, 0.0, 0.0), 0.5, gray())));
+        }
+        world.add(Arc::new(Triangle::new(
+            Point3::new(0.0, 3.0, 0.0),
+            Point3::new(1.0, 3.0, 0.0),
+            Point3::new(0.0, 4.0, 0.0),
+            gray(),
+        )));
+        world.add(Arc::new(Triangle::new(
+            Point3::new(0.0, -1.0, -5.0),
+            Point3::new(1.0, -1.0, -5.0),
+            Point3::new(0.0, -1.0, -4.0),
+            gray(),
+        )));
+
+        let summary = world.summary();
+        assert_eq!(summary.objects, 5);
+        assert_eq!(summary.unbounded, 0);
+        let by_type: Vec<_> = summary.by_type.into_iter().collect();
+        assert_eq!(by_type, [("Sphere", 3), ("Triangle", 2)]);
+
+        let bbox = summary.bbox.unwrap();
+        for object in &world {
+            let object_box = object.bounding_box().unwrap();
+            for axis in 0..3 {
+                assert!(bbox.min[axis] <= object_box.min[axis]);
+                assert!(bbox.max[axis] >= object_box.max[axis]);
+            }
+        }
+        assert_vec3_eq!(bbox.min, Point3::new(-2.5, -1.0, -5.0), 1e-3);
+        assert_vec3_eq!(bbox.max, Point3::new(2.5, 4.0, 0.5), 1e-3);
     }
 }
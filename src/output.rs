@@ -0,0 +1,69 @@
+use crate::hdr::HdrImage;
+use image::codecs::hdr::HdrEncoder;
+use image::{ImageResult, Rgb, RgbImage};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+// Picks the format from the extension: .ppm is written as binary PPM,
+// anything else goes through the image crate's encoders
+pub fn save_image<P: AsRef<Path>>(path: P, image: &RgbImage) -> ImageResult<()> {
+    let path = path.as_ref();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("ppm") => Ok(write_ppm(path, image)?),
+        _ => image.save(path),
+    }
+}
+
+// Binary (P6) PPM
+pub fn write_ppm<P: AsRef<Path>>(path: P, image: &RgbImage) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P6\n{} {}\n255\n", image.width(), image.height())?;
+    out.write_all(image.as_raw())?;
+    out.flush()
+}
+
+// ASCII (P3) PPM, one pixel per line
+pub fn write_ppm_ascii<P: AsRef<Path>>(path: P, image: &RgbImage) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write!(out, "P3\n{} {}\n255\n", image.width(), image.height())?;
+    for pixel in image.pixels() {
+        writeln!(out, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
+    }
+    out.flush()
+}
+
+// Radiance .hdr (RGBE), keeps the linear values above 1.0
+pub fn save_hdr<P: AsRef<Path>>(path: P, image: &HdrImage) -> ImageResult<()> {
+    let out = BufWriter::new(File::create(path)?);
+    let pixels: Vec<Rgb<f32>> = image.pixels().iter().map(|&p| Rgb(p)).collect();
+    HdrEncoder::new(out).encode(&pixels, image.width() as usize, image.height() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    // Reads `path` back with the image crate's decoder, then removes it
+    fn read_back(path: &Path) -> RgbImage {
+        let read = image::open(path).map(|read| read.into_rgb8());
+        std::fs::remove_file(path).unwrap();
+        read.unwrap()
+    }
+
+    #[test]
+    fn ppm_round_trips_through_a_reader() {
+        let image = RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8 * 80, y as u8 * 200, 7]));
+        let dir = std::env::temp_dir();
+        let id = std::process::id();
+
+        let binary = dir.join(format!("ray_tracing_binary_{}.ppm", id));
+        write_ppm(&binary, &image).unwrap();
+        assert_eq!(read_back(&binary), image);
+
+        let ascii = dir.join(format!("ray_tracing_ascii_{}.ppm", id));
+        write_ppm_ascii(&ascii, &image).unwrap();
+        assert_eq!(read_back(&ascii), image);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::la::{Onb, Point3, Vec3};
+use crate::objects::Hittable;
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+// A distribution over directions that can be both sampled and evaluated,
+// so a sample can be weighted by the density it was drawn with
+pub trait Pdf: Send + Sync {
+    // Density per solid angle of generate() picking `direction`
+    fn value(&self, direction: &Vec3) -> f64;
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3;
+}
+
+// Cosine-weighted around a normal, how Lambertian surfaces scatter
+pub struct CosinePdf {
+    uvw: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(w: &Vec3) -> CosinePdf {
+        CosinePdf {
+            uvw: Onb::from_w(w),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let cosine = Vec3::dot(&direction.as_unit_vector(), &self.uvw.w());
+        cosine.max(0.0) / PI
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        self.uvw.local(&Vec3::rand_cosine_direction(rng))
+    }
+}
+
+// Uniform over all directions, how isotropic media scatter
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        Vec3::rand_unit_vector(rng)
+    }
+}
+
+// Directions from `origin` towards an object, usually the lights
+pub struct HittablePdf<'a> {
+    object: &'a dyn Hittable,
+    origin: Point3,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(object: &'a dyn Hittable, origin: Point3) -> HittablePdf<'a> {
+        HittablePdf { object, origin }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.object.pdf_value(&self.origin, direction)
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        self.object.random(&self.origin, rng)
+    }
+}
+
+// Samples either component with equal probability, so its density is their
+// average
+pub struct MixturePdf<'a> {
+    pdfs: [&'a dyn Pdf; 2],
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(a: &'a dyn Pdf, b: &'a dyn Pdf) -> MixturePdf<'a> {
+        MixturePdf { pdfs: [a, b] }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * self.pdfs[0].value(direction) + 0.5 * self.pdfs[1].value(direction)
+    }
+
+    fn generate(&self, rng: &mut dyn RngCore) -> Vec3 {
+        if rng.gen_bool(0.5) {
+            self.pdfs[0].generate(rng)
+        } else {
+            self.pdfs[1].generate(rng)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn cosine_pdf_integrates_to_one() {
+        let pdf = CosinePdf::new(&Vec3::new(1.0, 2.0, -0.5));
+        let mut rng = SmallRng::seed_from_u64(11);
+        let n = 200_000;
+        // Uniform directions over the sphere, each standing for 4π / n of it
+        let total: f64 = (0..n)
+            .map(|_| pdf.value(&Vec3::rand_unit_vector(&mut rng)))
+            .sum();
+        let integral = total * 4.0 * PI / n as f64;
+        assert!((integral - 1.0).abs() < 0.01, "{}", integral);
+    }
+
+    #[test]
+    fn mixture_pdf_averages_its_components() {
+        let up = CosinePdf::new(&Vec3::new(0.0, 0.0, 1.0));
+        let down = CosinePdf::new(&Vec3::new(0.0, 0.0, -1.0));
+        let mixture = MixturePdf::new(&up, &down);
+        let mut rng = SmallRng::seed_from_u64(12);
+        for _ in 0..100 {
+            let direction = Vec3::rand_unit_vector(&mut rng);
+            let expected = 0.5 * up.value(&direction) + 0.5 * down.value(&direction);
+            assert!((mixture.value(&direction) - expected).abs() < 1e-12);
+        }
+
+        // Half the samples come from each side
+        let n = 10_000;
+        let upward = (0..n)
+            .filter(|_| mixture.generate(&mut rng).z() > 0.0)
+            .count();
+        assert!((upward as f64 / n as f64 - 0.5).abs() < 0.02, "{}", upward);
+    }
+}
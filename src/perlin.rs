@@ -0,0 +1,128 @@
+use crate::la::{Point3, Vec3};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+const POINT_COUNT: usize = 256;
+
+pub struct Perlin {
+    ranvec: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    // The same seed always gives the same noise
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let ranvec = (0..POINT_COUNT)
+            .map(|_| Vec3::rand(&mut rng, -1.0, 1.0).as_unit_vector())
+            .collect();
+
+        let mut perm = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(&mut rng);
+            p
+        };
+        let perm_x = perm();
+        let perm_y = perm();
+        let perm_z = perm();
+
+        Perlin {
+            ranvec,
+            perm_x,
+            perm_y,
+            perm_z,
+        }
+    }
+
+    // Gradient noise in roughly [-1, 1]
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let u = p.x() - p.x().floor();
+        let v = p.y() - p.y().floor();
+        let w = p.z() - p.z().floor();
+
+        let i = p.x().floor() as i64;
+        let j = p.y().floor() as i64;
+        let k = p.z().floor() as i64;
+
+        let mut c = [[[Vec3::new(0.0, 0.0, 0.0); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, corner) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize];
+                    *corner = self.ranvec[index];
+                }
+            }
+        }
+
+        Perlin::interp(&c, u, v, w)
+    }
+
+    // Sum of `depth` octaves of |noise|, each at double frequency and half weight
+    pub fn turb(&self, p: &Point3, depth: u32) -> f64 {
+        let mut accum = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(&temp_p);
+            weight *= 0.5;
+            temp_p = 2.0 * temp_p;
+        }
+
+        accum.abs()
+    }
+
+    fn interp(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+        // Hermite smoothing removes the grid artifacts of plain trilinear blending
+        let uu = u * u * (3.0 - 2.0 * u);
+        let vv = v * v * (3.0 - 2.0 * v);
+        let ww = w * w * (3.0 - 2.0 * w);
+
+        let mut accum = 0.0;
+        for (i, plane) in c.iter().enumerate() {
+            for (j, row) in plane.iter().enumerate() {
+                for (k, corner) in row.iter().enumerate() {
+                    let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                    let weight_v = Vec3::new(u - fi, v - fj, w - fk);
+                    accum += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * Vec3::dot(corner, &weight_v);
+                }
+            }
+        }
+        accum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid() -> impl Iterator<Item = Point3> {
+        (0..1000).map(|i| {
+            let (x, y, z) = (i % 10, i / 10 % 10, i / 100);
+            Point3::new(x as f64 * 0.37, y as f64 * 0.53 - 2.0, z as f64 * 0.71)
+        })
+    }
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let a = Perlin::new(7);
+        let b = Perlin::new(7);
+        for p in grid() {
+            let value = a.noise(&p);
+            assert_eq!(value, b.noise(&p));
+            assert!(value.abs() <= 1.0, "noise {} at {:?}", value, p);
+            assert!((0.0..2.0).contains(&a.turb(&p, 7)));
+        }
+
+        let other = Perlin::new(8);
+        assert!(grid().any(|p| a.noise(&p) != other.noise(&p)));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::hdr::HdrImage;
+use crate::la::Color;
+use image::{Rgb, RgbImage};
+use rayon::prelude::*;
+
+// Bilinear upscale by an integer factor. Samples sit at pixel centers and
+// lookups past the edge clamp to the border pixels.
+pub fn upscale(image: &RgbImage, factor: u32) -> RgbImage {
+    let factor = factor.max(1);
+    let (width, height) = (image.width(), image.height());
+    if factor == 1 || width == 0 || height == 0 {
+        return image.clone();
+    }
+
+    RgbImage::from_fn(width * factor, height * factor, |x, y| {
+        let sx = ((x as f64 + 0.5) / factor as f64 - 0.5).clamp(0.0, (width - 1) as f64);
+        let sy = ((y as f64 + 0.5) / factor as f64 - 0.5).clamp(0.0, (height - 1) as f64);
+        let (x0, y0) = (sx as u32, sy as u32);
+        let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+        let (tx, ty) = (sx - x0 as f64, sy - y0 as f64);
+
+        let mut pixel = [0; 3];
+        for (c, out) in pixel.iter_mut().enumerate() {
+            let top = lerp(image.get_pixel(x0, y0)[c], image.get_pixel(x1, y0)[c], tx);
+            let bottom = lerp(image.get_pixel(x0, y1)[c], image.get_pixel(x1, y1)[c], tx);
+            *out = (top + (bottom - top) * ty).round() as u8;
+        }
+        Rgb(pixel)
+    })
+}
+
+fn lerp(a: u8, b: u8, t: f64) -> f64 {
+    a as f64 + (b as f64 - a as f64) * t
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DenoiseParams {
+    // Neighbourhood is (2 * radius + 1)^2 pixels
+    pub radius: u32,
+    // Standard deviation of the spatial Gaussian, in pixels
+    pub sigma_spatial: f64,
+    // Standard deviation of the color difference weight for the bilateral
+    // filter, None for a plain Gaussian blur
+    pub sigma_color: Option<f64>,
+}
+
+impl DenoiseParams {
+    pub fn gaussian(sigma_spatial: f64) -> DenoiseParams {
+        DenoiseParams {
+            radius: (2.0 * sigma_spatial).ceil() as u32,
+            sigma_spatial,
+            sigma_color: None,
+        }
+    }
+
+    // Neighbours whose color differs a lot from the pixel count for little,
+    // so edges stay sharp
+    pub fn bilateral(sigma_spatial: f64, sigma_color: f64) -> DenoiseParams {
+        DenoiseParams {
+            sigma_color: Some(sigma_color),
+            ..DenoiseParams::gaussian(sigma_spatial)
+        }
+    }
+}
+
+// Weighted average of each pixel's neighbourhood, ignoring pixels past the edge
+pub fn denoise(image: &HdrImage, params: &DenoiseParams) -> HdrImage {
+    let (width, height) = (image.width(), image.height());
+    let radius = params.radius as i64;
+    let mut out = HdrImage::new(width, height);
+
+    out.pixels_mut()
+        .par_chunks_mut(width.max(1) as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                let center = image.get_pixel(x as u32, y as u32);
+                let mut sum = Color::new(0.0, 0.0, 0.0);
+                let mut weight_sum = 0.0;
+
+                for dy in -radius..=radius {
+                    for dx in -radius..=radius {
+                        let (sx, sy) = (x as i64 + dx, y as i64 + dy);
+                        if sx < 0 || sy < 0 || sx >= width as i64 || sy >= height as i64 {
+                            continue;
+                        }
+
+                        let color = image.get_pixel(sx as u32, sy as u32);
+                        let distance_squared = (dx * dx + dy * dy) as f64;
+                        let mut weight =
+                            f64::exp(-distance_squared / (2.0 * params.sigma_spatial.powi(2)));
+                        if let Some(sigma_color) = params.sigma_color {
+                            let difference = (color - center).length_squared();
+                            weight *= f64::exp(-difference / (2.0 * sigma_color.powi(2)));
+                        }
+
+                        sum += weight * color;
+                        weight_sum += weight;
+                    }
+                }
+
+                *pixel = (sum / weight_sum).to_f32_array();
+            }
+        });
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubling_2x2_interpolates_the_center_and_clamps_the_edges() {
+        // Red rises left to right, green top to bottom
+        let image = RgbImage::from_fn(2, 2, |x, y| Rgb([x as u8 * 200, y as u8 * 100, 9]));
+        let big = upscale(&image, 2);
+        assert_eq!(big.dimensions(), (4, 4));
+
+        assert_eq!(big.get_pixel(1, 1).0, [50, 25, 9]);
+        assert_eq!(big.get_pixel(2, 1).0, [150, 25, 9]);
+        assert_eq!(big.get_pixel(1, 2).0, [50, 75, 9]);
+        assert_eq!(big.get_pixel(2, 2).0, [150, 75, 9]);
+
+        assert_eq!(big.get_pixel(0, 0).0, [0, 0, 9]);
+        assert_eq!(big.get_pixel(3, 3).0, [200, 100, 9]);
+    }
+
+    #[test]
+    fn gaussian_smooths_a_spike_and_bilateral_keeps_an_edge() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let mut spike = HdrImage::new(9, 9);
+        for y in 0..9 {
+            for x in 0..9 {
+                spike.put_pixel(x, y, gray);
+            }
+        }
+        spike.put_pixel(4, 4, Color::new(10.0, 10.0, 10.0));
+        let smoothed = denoise(&spike, &DenoiseParams::gaussian(1.0));
+        assert!(smoothed.get_pixel(4, 4).x() < 3.0);
+        assert!(smoothed.get_pixel(5, 4).x() > gray.x());
+
+        // Black on the left, white on the right
+        let mut edge = HdrImage::new(8, 8);
+        for y in 0..8 {
+            for x in 4..8 {
+                edge.put_pixel(x, y, Color::new(1.0, 1.0, 1.0));
+            }
+        }
+        let blurred = denoise(&edge, &DenoiseParams::gaussian(1.0));
+        let kept = denoise(&edge, &DenoiseParams::bilateral(1.0, 0.1));
+        assert!(blurred.get_pixel(3, 4).x() > 0.1);
+        assert!(kept.get_pixel(3, 4).x() < 1e-6);
+        assert!(kept.get_pixel(4, 4).x() > 1.0 - 1e-6);
+    }
+}
@@ -0,0 +1,77 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Thread-safe count of finished work. Draws a bar with an ETA on a terminal
+// and falls back to a line every 10% when stderr is redirected.
+pub struct Progress {
+    total: u64,
+    done: AtomicU64,
+    bar: Option<ProgressBar>,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(total: u64, enabled: bool) -> Progress {
+        let bar = (enabled && std::io::stderr().is_terminal()).then(|| {
+            let bar = ProgressBar::new(total);
+            bar.set_style(
+                ProgressStyle::with_template("{wide_bar} {percent:>3}% [{elapsed} < {eta}]")
+                    .unwrap(),
+            );
+            bar
+        });
+
+        Progress {
+            total,
+            done: AtomicU64::new(0),
+            bar,
+            enabled,
+        }
+    }
+
+    pub fn inc(&self, n: u64) {
+        let before = self.done.fetch_add(n, Ordering::Relaxed);
+        let after = before + n;
+
+        if let Some(bar) = &self.bar {
+            bar.inc(n);
+        } else if self.enabled
+            && self.total > 0
+            && before * 10 / self.total != after * 10 / self.total
+        {
+            eprintln!("Rendered {}%", after * 100 / self.total);
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish();
+        }
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn parallel_rows_count_up_to_the_total() {
+        let (width, height) = (37, 23);
+        let progress = Progress::new(width * height, false);
+        (0..height)
+            .into_par_iter()
+            .for_each(|_| progress.inc(width));
+        progress.finish();
+        assert_eq!(progress.done(), progress.total());
+    }
+}
@@ -0,0 +1,1680 @@
+use crate::background::{Background, Sky};
+use crate::bvh::BvhNode;
+use crate::camera::Camera;
+use crate::checkpoint::{Accumulation, CheckpointConfig};
+use crate::hdr::HdrImage;
+use crate::la::{splitmix64, Color, ColorSpace, Point3, Ray, ToneMap, Vec3};
+use crate::objects::{Hittable, HittableList, Material, ScatterKind, ScatterRecord};
+use crate::pdf::{HittablePdf, MixturePdf, Pdf};
+use crate::post::{denoise, DenoiseParams};
+use crate::progress::Progress;
+use crate::stats::{self, RenderStats, StatsAccumulator};
+use image::RgbImage;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RenderMode {
+    // Full path tracing
+    Shaded,
+    // Surface normal of the first hit as 0.5 * (normal + 1)
+    Normals,
+    // Distance to the first hit, white at `near` fading to black at `far`
+    Depth { near: f64, far: f64 },
+    // Number of bounding boxes the camera ray passes through, black at 0 and
+    // white at `max` or more
+    BoxHeatmap { max: u32 },
+}
+
+// Auxiliary buffers (AOVs) for compositing and denoising, taken at the first
+// non-specular surface each camera ray reaches and averaged like the beauty
+// pass
+pub struct RenderPasses {
+    pub beauty: HdrImage,
+    pub albedo: HdrImage,
+    // World space, facing the incoming ray; (0, 0, 0) where nothing was hit
+    pub normal: HdrImage,
+    // Row-major camera-to-surface path lengths, averaged over the samples
+    // that hit something and infinite where none did
+    pub depth: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub image_width: u32,
+    pub image_height: u32,
+    pub samples_per_pixel: u32,
+    // Adaptive sampling, a tolerance of 0.0 always takes samples_per_pixel
+    pub min_samples_per_pixel: u32,
+    pub sample_tolerance: f64,
+    pub max_depth: u32,
+    // Hits closer than this to a ray's origin are ignored, so bounced rays
+    // don't re-hit the surface they start on (shadow acne). Large scenes may
+    // need more, small detailed ones less.
+    pub t_min: f64,
+    // Same seed, size and sample count gives an identical image
+    pub seed: u64,
+    // Jitter samples over a grid of sub-pixel cells instead of purely at random
+    pub stratified: bool,
+    pub mode: RenderMode,
+    // How shaded renders squeeze colors above 1.0 into the 8-bit image
+    pub tone_map: ToneMap,
+    // Only trace pixels with x0 <= x < x1 and y0 <= y < y1, counted from the
+    // top-left, and leave the rest black. The framing is that of the full
+    // image.
+    pub crop: Option<(u32, u32, u32, u32)>,
+    // Output encoding of shaded renders, debug modes are always linear
+    pub color_space: ColorSpace,
+    // Scale down samples brighter than this luminance before averaging, to
+    // hide fireflies from caustics and small lights. Biased, so off by
+    // default.
+    pub firefly_clamp: Option<f64>,
+    // Whether worlds given as a HittableList (Renderer::from_list() and
+    // set_world()) get a BVH built over them. Off by default.
+    pub bvh: bool,
+    // Brightens (positive) or darkens shaded renders by this many stops
+    // before tone mapping, each one doubling the linear color
+    pub exposure_ev: f64,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            image_width: 400,
+            image_height: 225,
+            samples_per_pixel: 100,
+            min_samples_per_pixel: 32,
+            sample_tolerance: 0.0,
+            max_depth: 50,
+            t_min: 0.001,
+            seed: 0,
+            stratified: true,
+            mode: RenderMode::Shaded,
+            tone_map: ToneMap::Clamp,
+            crop: None,
+            color_space: ColorSpace::Gamma(2.0),
+            firefly_clamp: None,
+            bvh: false,
+            exposure_ev: 0.0,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+
+    // Linear multiplier for `exposure_ev`
+    pub fn exposure(&self) -> f64 {
+        2f64.powf(self.exposure_ev)
+    }
+
+    // Whether the pixel at (x, y) is traced, see `crop`
+    pub fn in_crop(&self, x: u32, y: u32) -> bool {
+        match self.crop {
+            Some((x0, y0, x1, y1)) => (x0..x1).contains(&x) && (y0..y1).contains(&y),
+            None => true,
+        }
+    }
+}
+
+pub struct Renderer {
+    pub camera: Camera,
+    pub world: Arc<dyn Hittable>,
+    pub background: Arc<dyn Background>,
+    // Emitters to aim diffuse bounces at (next event estimation). They must
+    // also be in the world to be seen.
+    pub lights: Option<Arc<dyn Hittable>>,
+    pub settings: RenderSettings,
+    pub show_progress: bool,
+    // Post-process filter over the HDR result, off by default
+    pub denoise: Option<DenoiseParams>,
+    // Only used by render_checkpointed() and resume_from()
+    pub checkpoint: Option<CheckpointConfig>,
+    // Shades every surface with this instead of its own material, e.g. a
+    // flat Lambertian for a clay render. The scene itself is left alone.
+    pub override_material: Option<Arc<dyn Material>>,
+}
+
+impl Renderer {
+    // The image size in `settings` decides the aspect ratio, the camera's
+    // viewport is refitted to it
+    pub fn new(mut camera: Camera, world: Arc<dyn Hittable>, settings: RenderSettings) -> Renderer {
+        camera.set_aspect_ratio(settings.aspect_ratio());
+        Renderer {
+            camera,
+            world,
+            background: Arc::new(Sky),
+            lights: None,
+            settings,
+            show_progress: true,
+            denoise: None,
+            checkpoint: None,
+            override_material: None,
+        }
+    }
+
+    // Like new(), building a BVH over `world` if settings.bvh is set
+    pub fn from_list(camera: Camera, world: HittableList, settings: RenderSettings) -> Renderer {
+        let mut renderer = Renderer::new(camera, Arc::new(HittableList::new()), settings);
+        renderer.set_world(world);
+        renderer
+    }
+
+    // Replaces the world, building a BVH over it if settings.bvh is set.
+    // Unbounded objects like planes are checked alongside the tree.
+    pub fn set_world(&mut self, world: HittableList) {
+        self.world = if self.settings.bvh && !world.is_empty() {
+            Arc::new(BvhNode::new(&mut world.objects().to_vec()))
+        } else {
+            Arc::new(world)
+        };
+    }
+
+    pub fn render(&self) -> RgbImage {
+        self.tone_map(&self.render_hdr())
+    }
+
+    // Renders and reports how many rays and hit calls it took. The counts are
+    // only gathered when built with `--features stats`, otherwise they're zero.
+    pub fn render_with_stats(&self) -> (RgbImage, RenderStats) {
+        let stats = StatsAccumulator::default();
+        let image = self.tone_map(&self.render_hdr_counted(&stats));
+        (image, stats.total())
+    }
+
+    // Linear pixel colors before tone mapping, values above 1.0 are kept
+    pub fn render_hdr(&self) -> HdrImage {
+        self.render_hdr_counted(&StatsAccumulator::default())
+    }
+
+    fn render_hdr_counted(&self, stats: &StatsAccumulator) -> HdrImage {
+        let settings = &self.settings;
+        let mut image = HdrImage::new(settings.image_width, settings.image_height);
+        let strata = Stratifier::new(settings.samples_per_pixel, settings.stratified);
+        let progress = Progress::new(
+            settings.image_width as u64 * settings.image_height as u64,
+            self.show_progress,
+        );
+
+        image
+            .pixels_mut()
+            .par_chunks_mut(settings.image_width as usize)
+            .enumerate()
+            .for_each(|(y, pixels)| {
+                stats.count(|| {
+                    for (x, pixel) in pixels.iter_mut().enumerate() {
+                        *pixel = self
+                            .render_pixel(x as u32, y as u32, &strata)
+                            .to_f32_array();
+                    }
+                });
+
+                progress.inc(settings.image_width as u64);
+            });
+
+        progress.finish();
+        self.post_process(image)
+    }
+
+    // The render_hdr() image plus albedo, normal and depth buffers, gathered
+    // from the same samples
+    pub fn render_passes(&self) -> RenderPasses {
+        let settings = &self.settings;
+        let (width, height) = (settings.image_width, settings.image_height);
+        let mut beauty = HdrImage::new(width, height);
+        let mut albedo = HdrImage::new(width, height);
+        let mut normal = HdrImage::new(width, height);
+        let mut depth = vec![0.0; width as usize * height as usize];
+        let strata = Stratifier::new(settings.samples_per_pixel, settings.stratified);
+        let progress = Progress::new(width as u64 * height as u64, self.show_progress);
+
+        beauty
+            .pixels_mut()
+            .par_chunks_mut(width as usize)
+            .zip(albedo.pixels_mut().par_chunks_mut(width as usize))
+            .zip(normal.pixels_mut().par_chunks_mut(width as usize))
+            .zip(depth.par_chunks_mut(width as usize))
+            .enumerate()
+            .for_each(|(y, (((beauty, albedo), normal), depth))| {
+                for x in 0..width as usize {
+                    let (pixel_color, pixel_albedo, pixel_normal, pixel_depth) =
+                        self.render_pixel_passes(x as u32, y as u32, &strata);
+                    beauty[x] = pixel_color.to_f32_array();
+                    albedo[x] = pixel_albedo.to_f32_array();
+                    normal[x] = pixel_normal.to_f32_array();
+                    depth[x] = pixel_depth;
+                }
+
+                progress.inc(width as u64);
+            });
+
+        progress.finish();
+        RenderPasses {
+            beauty: self.post_process(beauty),
+            albedo,
+            normal,
+            depth,
+        }
+    }
+
+    // Same image as render(), but the work is split into tile_size x tile_size
+    // tiles that worker threads pull from a shared queue
+    pub fn render_tiled(&self, tile_size: u32) -> RgbImage {
+        self.tone_map(&self.render_tiled_hdr(tile_size))
+    }
+
+    pub fn render_tiled_hdr(&self, tile_size: u32) -> HdrImage {
+        let settings = &self.settings;
+        let (width, height) = (settings.image_width, settings.image_height);
+        let tile_size = tile_size.max(1);
+        let strata = Stratifier::new(settings.samples_per_pixel, settings.stratified);
+        let progress = Progress::new(width as u64 * height as u64, self.show_progress);
+
+        let tiles: Vec<(u32, u32)> = (0..height)
+            .step_by(tile_size as usize)
+            .flat_map(|y0| {
+                (0..width)
+                    .step_by(tile_size as usize)
+                    .map(move |x0| (x0, y0))
+            })
+            .collect();
+        let next_tile = AtomicUsize::new(0);
+        let image = Mutex::new(HdrImage::new(width, height));
+
+        rayon::scope(|scope| {
+            for _ in 0..rayon::current_num_threads() {
+                scope.spawn(|_| {
+                    while let Some(&(x0, y0)) = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed))
+                    {
+                        let x1 = (x0 + tile_size).min(width);
+                        let y1 = (y0 + tile_size).min(height);
+
+                        // Trace without holding the lock, then copy the tile in
+                        let tile: Vec<(u32, u32, Color)> = (y0..y1)
+                            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+                            .map(|(x, y)| (x, y, self.render_pixel(x, y, &strata)))
+                            .collect();
+
+                        let mut image = image.lock().unwrap();
+                        for &(x, y, color) in &tile {
+                            image.put_pixel(x, y, color);
+                        }
+                        drop(image);
+
+                        progress.inc(tile.len() as u64);
+                    }
+                });
+            }
+        });
+
+        progress.finish();
+        self.post_process(image.into_inner().unwrap())
+    }
+
+    fn post_process(&self, image: HdrImage) -> HdrImage {
+        match &self.denoise {
+            Some(params) => denoise(&image, params),
+            None => image,
+        }
+    }
+
+    // Distance to, and position of, the nearest surface under (s, t) on the
+    // screen, both in [0, 1] from the bottom-left like Camera::get_ray().
+    // A single ray without shading, for picking objects. With depth of field
+    // the lens position is fixed, so repeated picks agree.
+    pub fn pick(&self, s: f64, t: f64) -> Option<(f64, Point3)> {
+        let mut rng = SmallRng::seed_from_u64(self.settings.seed);
+        let r = self.camera.get_ray_rng(s, t, &mut rng);
+        let rec = self.world.hit(&r, self.settings.t_min, f64::INFINITY)?;
+        Some((rec.t * r.direction().length(), rec.p))
+    }
+
+    // Converts a render_hdr() result to the 8-bit image render() returns
+    pub fn tone_map(&self, hdr: &HdrImage) -> RgbImage {
+        // Debug modes write their values linearly and unexposed
+        let (exposure, tone_map, color_space) = if self.settings.mode == RenderMode::Shaded {
+            (
+                self.settings.exposure(),
+                self.settings.tone_map,
+                self.settings.color_space,
+            )
+        } else {
+            (1.0, ToneMap::Clamp, ColorSpace::Gamma(1.0))
+        };
+        hdr.exposed(exposure).to_rgb_image(tone_map, color_space)
+    }
+
+    // Renders with checkpoints: every `checkpoint.interval` the samples taken
+    // so far are saved to `checkpoint.path`, and once more when done, so
+    // resume_from() can pick the render back up or add more samples to it.
+    // Every pixel takes the full samples_per_pixel, adaptive sampling and
+    // stratification are off.
+    pub fn render_checkpointed(&self) -> io::Result<HdrImage> {
+        let settings = &self.settings;
+        self.continue_render(Accumulation::new(
+            settings.image_width,
+            settings.image_height,
+        ))
+    }
+
+    // Continues a checkpoint up to samples_per_pixel. The image size must
+    // match the checkpoint's.
+    pub fn resume_from<P: AsRef<Path>>(&self, path: P) -> io::Result<HdrImage> {
+        let accumulation = Accumulation::load(path)?;
+        if accumulation.width() != self.settings.image_width
+            || accumulation.height() != self.settings.image_height
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint is {}x{}, render is {}x{}",
+                    accumulation.width(),
+                    accumulation.height(),
+                    self.settings.image_width,
+                    self.settings.image_height
+                ),
+            ));
+        }
+
+        self.continue_render(accumulation)
+    }
+
+    fn continue_render(&self, mut accumulation: Accumulation) -> io::Result<HdrImage> {
+        let settings = &self.settings;
+        let remaining = settings
+            .samples_per_pixel
+            .saturating_sub(accumulation.samples());
+        let progress = Progress::new(
+            remaining as u64 * settings.image_width as u64 * settings.image_height as u64,
+            self.show_progress,
+        );
+
+        let mut last_save = Instant::now();
+        while accumulation.samples() < settings.samples_per_pixel {
+            self.render_samples(&mut accumulation, 1);
+            progress.inc(settings.image_width as u64 * settings.image_height as u64);
+
+            if let Some(checkpoint) = &self.checkpoint {
+                if last_save.elapsed() >= checkpoint.interval {
+                    accumulation.save(&checkpoint.path)?;
+                    last_save = Instant::now();
+                }
+            }
+        }
+        progress.finish();
+
+        if let Some(checkpoint) = &self.checkpoint {
+            accumulation.save(&checkpoint.path)?;
+        }
+        Ok(self.post_process(accumulation.to_hdr_image()))
+    }
+
+    // Renders `passes` passes of one sample per pixel, averaging them as it
+    // goes and handing the image so far to `on_pass` along with the number of
+    // passes done. The end result matches render() with samples_per_pixel =
+    // passes and adaptive sampling off.
+    pub fn render_progressive(
+        &self,
+        passes: u32,
+        mut on_pass: impl FnMut(&RgbImage, usize),
+    ) -> HdrImage {
+        let settings = &self.settings;
+        let strata = Stratifier::new(passes, settings.stratified);
+        let mut accumulation = Accumulation::new(settings.image_width, settings.image_height);
+        let pixels = settings.image_width as u64 * settings.image_height as u64;
+        let progress = Progress::new(passes as u64 * pixels, self.show_progress);
+
+        for pass in 1..=passes {
+            self.accumulate(&mut accumulation, 1, &strata);
+            progress.inc(pixels);
+            on_pass(&self.tone_map(&accumulation.to_hdr_image()), pass as usize);
+        }
+        progress.finish();
+
+        self.post_process(accumulation.to_hdr_image())
+    }
+
+    // Adds the next `count` samples to every pixel of `accumulation`. They
+    // aren't stratified, since the grid depends on the final sample count and
+    // a resumed render may ask for more samples than it started with.
+    pub fn render_samples(&self, accumulation: &mut Accumulation, count: u32) {
+        self.accumulate(accumulation, count, &Stratifier::new(0, false));
+    }
+
+    fn accumulate(&self, accumulation: &mut Accumulation, count: u32, strata: &Stratifier) {
+        let settings = &self.settings;
+        let first = accumulation.samples();
+
+        accumulation
+            .sums_mut()
+            .par_chunks_mut(settings.image_width as usize)
+            .enumerate()
+            .for_each(|(y, sums)| {
+                for (x, sum) in sums.iter_mut().enumerate() {
+                    if !settings.in_crop(x as u32, y as u32) {
+                        continue;
+                    }
+
+                    let mut color = Color::from(*sum);
+                    for index in first..first + count {
+                        color += self.sample(x as u32, y as u32, index, strata);
+                    }
+                    *sum = color.into();
+                }
+            });
+
+        accumulation.add_samples(count);
+    }
+
+    // Average color of the pixel at (x, y), counting from the top-left of the
+    // image
+    fn render_pixel(&self, x: u32, y: u32, strata: &Stratifier) -> Color {
+        let settings = &self.settings;
+        if !settings.in_crop(x, y) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let (pixel_color, samples) = sample_pixel(
+            |index| self.sample(x, y, index, strata),
+            settings.min_samples_per_pixel,
+            settings.samples_per_pixel,
+            settings.sample_tolerance,
+        );
+
+        pixel_color / samples as f64
+    }
+
+    // Average color, albedo and normal and the depth of the pixel at (x, y).
+    // The surface walk gets a copy of each sample's RNG, so the beauty pass
+    // is the same as render_pixel's.
+    fn render_pixel_passes(
+        &self,
+        x: u32,
+        y: u32,
+        strata: &Stratifier,
+    ) -> (Color, Color, Vec3, f32) {
+        let settings = &self.settings;
+        if !settings.in_crop(x, y) {
+            let black = Color::new(0.0, 0.0, 0.0);
+            return (black, black, Vec3::new(0.0, 0.0, 0.0), f32::INFINITY);
+        }
+
+        let mut albedo = Color::new(0.0, 0.0, 0.0);
+        let mut normal = Vec3::new(0.0, 0.0, 0.0);
+        let mut distance = 0.0;
+        let mut hits = 0;
+
+        let (pixel_color, samples) = sample_pixel(
+            |index| {
+                let (r, mut rng) = self.camera_ray(x, y, index, strata);
+                let mut surface_rng = rng.clone();
+                let (surface_albedo, surface) = first_surface(
+                    &r,
+                    self.world.as_ref(),
+                    self.background.as_ref(),
+                    self.override_material.as_deref(),
+                    settings.t_min,
+                    settings.max_depth,
+                    &mut surface_rng,
+                );
+
+                albedo += surface_albedo;
+                if let Some((surface_normal, surface_distance)) = surface {
+                    normal += surface_normal;
+                    distance += surface_distance;
+                    hits += 1;
+                }
+                self.shade(&r, &mut rng)
+            },
+            settings.min_samples_per_pixel,
+            settings.samples_per_pixel,
+            settings.sample_tolerance,
+        );
+
+        let n = samples as f64;
+        let depth = if hits > 0 {
+            (distance / hits as f64) as f32
+        } else {
+            f32::INFINITY
+        };
+        (pixel_color / n, albedo / n, normal / n, depth)
+    }
+
+    // Sample `index` of the pixel at (x, y). Each sample gets its own RNG, so
+    // the result doesn't depend on the order pixels are rendered in or on how
+    // a pixel's samples are split between passes.
+    fn sample(&self, x: u32, y: u32, index: u32, strata: &Stratifier) -> Color {
+        let (r, mut rng) = self.camera_ray(x, y, index, strata);
+        self.shade(&r, &mut rng)
+    }
+
+    // The camera ray for sample `index` of the pixel at (x, y), and the RNG
+    // to continue its path with
+    fn camera_ray(&self, x: u32, y: u32, index: u32, strata: &Stratifier) -> (Ray, SmallRng) {
+        let settings = &self.settings;
+        let mut rng = SmallRng::seed_from_u64(seed_for_sample(x, y, index, settings.seed));
+        let j = settings.image_height - y - 1;
+
+        let (du, dv) = strata.offset(index, &mut rng);
+        let u = (x as f64 + du) / (settings.image_width - 1) as f64;
+        let v = (j as f64 + dv) / (settings.image_height - 1) as f64;
+        let r = self.camera.get_ray_rng(u, v, &mut rng);
+        stats::count_primary_ray();
+
+        (r, rng)
+    }
+
+    fn shade(&self, r: &Ray, rng: &mut SmallRng) -> Color {
+        let settings = &self.settings;
+        match settings.mode {
+            RenderMode::Shaded => {
+                let color = ray_color(
+                    r,
+                    self.world.as_ref(),
+                    self.background.as_ref(),
+                    self.lights.as_deref(),
+                    self.override_material.as_deref(),
+                    settings.t_min,
+                    settings.max_depth,
+                    rng,
+                );
+                match settings.firefly_clamp {
+                    Some(max) => clamp_luminance(color, max),
+                    None => color,
+                }
+            }
+            mode => debug_color(r, self.world.as_ref(), settings.t_min, mode),
+        }
+    }
+}
+
+// Scales `color` down to luminance `max` if it's brighter, keeping its hue
+pub fn clamp_luminance(color: Color, max: f64) -> Color {
+    let luminance = color.luminance();
+    if luminance > max {
+        color * (max / luminance)
+    } else {
+        color
+    }
+}
+
+// Mixes the pixel position into the render seed, so neighbouring pixels get
+// unrelated random streams
+pub fn seed_for_pixel(x: u32, y: u32, seed: u64) -> u64 {
+    splitmix64(seed ^ (((y as u64) << 32) | x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+pub fn seed_for_sample(x: u32, y: u32, index: u32, seed: u64) -> u64 {
+    splitmix64(seed_for_pixel(x, y, seed) ^ index as u64)
+}
+
+// Render seed of an animation's `frame`
+pub fn seed_for_frame(frame: u32, seed: u64) -> u64 {
+    splitmix64(seed.wrapping_add(frame as u64))
+}
+
+// Bounces every path takes before russian roulette may end it
+const ROULETTE_MIN_BOUNCES: u32 = 3;
+
+// Follows a path until it misses, is absorbed or runs out of bounces,
+// accumulating emission weighted by the attenuation along the way.
+// With `lights`, or a background that can be sampled, non-specular bounces
+// go towards them half of the time and are weighted by the mixed density.
+// Lights that can't be sampled (see Hittable::can_sample) are only found by
+// the material's own bounces.
+// `override_material`, if given, shades every hit in place of the surface's
+// own material.
+#[allow(clippy::too_many_arguments)]
+pub fn ray_color<R: Rng>(
+    r: &Ray,
+    world: &dyn Hittable,
+    background: &dyn Background,
+    lights: Option<&dyn Hittable>,
+    override_material: Option<&dyn Material>,
+    t_min: f64,
+    depth: u32,
+    rng: &mut R,
+) -> Color {
+    let mut color = Color::new(0.0, 0.0, 0.0);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut r = *r;
+    let background_pdf = background.pdf();
+
+    for bounce in 0..depth {
+        match world.hit(&r, t_min, f64::INFINITY) {
+            Some(rec) => {
+                let channel = r.channel();
+                let mat = override_material.unwrap_or(rec.mat.as_ref());
+                color += throughput * mat.emitted(&r, &rec);
+                let srec = match mat.scatter(&r, &rec, rng) {
+                    Some(srec) => srec,
+                    None => return color,
+                };
+
+                match srec.kind {
+                    ScatterKind::Specular(r_specular) => {
+                        throughput *= srec.attenuation;
+                        r = r_specular;
+                    }
+                    ScatterKind::Scattering(material_pdf) => {
+                        // Lights and the background's bright parts, if they
+                        // can be sampled, share half of the bounces
+                        let light_pdf = lights
+                            .filter(|lights| lights.can_sample())
+                            .map(|lights| HittablePdf::new(lights, rec.p));
+                        let targets;
+                        let target: Option<&dyn Pdf> = match (&light_pdf, background_pdf) {
+                            (Some(light_pdf), Some(background_pdf)) => {
+                                targets = MixturePdf::new(light_pdf, background_pdf);
+                                Some(&targets)
+                            }
+                            (Some(light_pdf), None) => Some(light_pdf),
+                            (None, background_pdf) => background_pdf,
+                        };
+
+                        let mixture;
+                        let pdf: &dyn Pdf = match target {
+                            Some(target) => {
+                                mixture = MixturePdf::new(target, material_pdf.as_ref());
+                                &mixture
+                            }
+                            None => material_pdf.as_ref(),
+                        };
+
+                        // Light samples point at the light rather than one
+                        // unit along, and t_min is only a distance for unit
+                        // directions
+                        let direction = pdf.generate(rng).as_unit_vector();
+                        let r_scattered = Ray::new_timed(rec.p, direction, r.time());
+                        let pdf_value = pdf.value(&direction);
+                        if pdf_value.is_nan() || pdf_value <= 0.0 {
+                            return color;
+                        }
+
+                        let scattering_pdf = mat.scattering_pdf(&r, &rec, &r_scattered);
+                        throughput *= srec.attenuation * scattering_pdf / pdf_value;
+                        r = r_scattered;
+                    }
+                }
+                // Once dispersive glass has picked a color for the path, later
+                // glass has to refract that same color
+                r = r.with_channel(r.channel().or(channel));
+
+                stats::count_scatter_ray();
+
+                // Russian roulette: dim paths are likely to be cut short, and
+                // the survivors are boosted by 1 / p so the estimate stays
+                // unbiased
+                if bounce >= ROULETTE_MIN_BOUNCES {
+                    let p = throughput.component_max().min(1.0);
+                    if rng.gen::<f64>() >= p {
+                        return color;
+                    }
+                    throughput /= p;
+                }
+            }
+            None => return color + throughput * background.color(&r.unit_direction()),
+        }
+    }
+
+    color
+}
+
+// Albedo at the first non-specular surface along `r`, with its normal and
+// the path length to it unless the path escapes. Mirror and glass bounces
+// are followed and tint the albedo, since they show what's behind them.
+// Escaped paths take the background as albedo.
+fn first_surface<R: Rng>(
+    r: &Ray,
+    world: &dyn Hittable,
+    background: &dyn Background,
+    override_material: Option<&dyn Material>,
+    t_min: f64,
+    depth: u32,
+    rng: &mut R,
+) -> (Color, Option<(Vec3, f64)>) {
+    let mut tint = Color::new(1.0, 1.0, 1.0);
+    let mut distance = 0.0;
+    let mut r = *r;
+
+    for _ in 0..depth {
+        let rec = match world.hit(&r, t_min, f64::INFINITY) {
+            Some(rec) => rec,
+            None => {
+                let albedo = tint * background.color(&r.unit_direction()).clamp(0.0, 1.0);
+                return (albedo, None);
+            }
+        };
+        distance += rec.t * r.direction().length();
+
+        let mat = override_material.unwrap_or(rec.mat.as_ref());
+        match mat.scatter(&r, &rec, rng) {
+            Some(ScatterRecord {
+                attenuation,
+                kind: ScatterKind::Specular(scattered),
+            }) => {
+                tint *= attenuation;
+                r = scattered;
+            }
+            Some(srec) => return (tint * srec.attenuation, Some((rec.normal, distance))),
+            None => {
+                let emitted = mat.emitted(&r, &rec).clamp(0.0, 1.0);
+                return (tint * emitted, Some((rec.normal, distance)));
+            }
+        }
+    }
+
+    (Color::new(0.0, 0.0, 0.0), None)
+}
+
+// Colors a ray by the geometry it first hits, ignoring materials and lights.
+// Misses are black. The box heatmap looks at bounding boxes instead of hits.
+pub fn debug_color(r: &Ray, world: &dyn Hittable, t_min: f64, mode: RenderMode) -> Color {
+    if let RenderMode::BoxHeatmap { max } = mode {
+        let shade = (world.box_hits(r, t_min, f64::INFINITY) as f64 / max as f64).min(1.0);
+        return Color::new(shade, shade, shade);
+    }
+
+    let rec = match world.hit(r, t_min, f64::INFINITY) {
+        Some(rec) => rec,
+        None => return Color::new(0.0, 0.0, 0.0),
+    };
+
+    match mode {
+        RenderMode::Shaded | RenderMode::Normals => 0.5 * (rec.normal + 1.0),
+        RenderMode::Depth { near, far } => {
+            let distance = rec.t * r.direction().length();
+            let shade = 1.0 - ((distance - near) / (far - near)).clamp(0.0, 1.0);
+            Color::new(shade, shade, shade)
+        }
+        RenderMode::BoxHeatmap { .. } => unreachable!(),
+    }
+}
+
+// Adaptive sampling: keeps drawing samples until the standard error of each
+// channel's mean falls below `tolerance` (but takes at least `min_samples`),
+// or `max_samples` is reached. `sample` gets the index of the sample to take.
+// Returns the summed color and the sample count.
+pub fn sample_pixel<F: FnMut(u32) -> Color>(
+    mut sample: F,
+    min_samples: u32,
+    max_samples: u32,
+    tolerance: f64,
+) -> (Color, u32) {
+    let mut sum = Color::new(0.0, 0.0, 0.0);
+    // Welford's running mean and sum of squared deviations
+    let mut mean = Color::new(0.0, 0.0, 0.0);
+    let mut m2 = Color::new(0.0, 0.0, 0.0);
+    let mut n = 0;
+
+    while n < max_samples {
+        let color = sample(n);
+        n += 1;
+        sum += color;
+
+        let delta = color - mean;
+        mean += delta / n as f64;
+        m2 += delta * (color - mean);
+
+        if n >= min_samples.max(2) {
+            let variance_of_mean = m2 / ((n - 1) as f64 * n as f64);
+            if variance_of_mean.component_max() < tolerance * tolerance {
+                break;
+            }
+        }
+    }
+
+    (sum, n)
+}
+
+// Jittered (stratified) sampling: the pixel is split into an n x n grid with
+// n * n <= samples_per_pixel, and sample k lands at a random spot in one cell.
+// Cells are visited with a stride coprime to the cell count, so a pixel that
+// adaptive sampling stops early still has samples spread over its whole area.
+// Samples past the grid, or all of them when disabled, are uniformly random.
+pub struct Stratifier {
+    n: u32,
+    stride: u32,
+}
+
+impl Stratifier {
+    pub fn new(samples_per_pixel: u32, enabled: bool) -> Stratifier {
+        let n = if enabled {
+            (samples_per_pixel as f64).sqrt() as u32
+        } else {
+            0
+        };
+
+        let cells = n * n;
+        let mut stride = ((cells as f64 * 0.618) as u32).max(1);
+        while gcd(stride, cells.max(1)) != 1 {
+            stride += 1;
+        }
+
+        Stratifier { n, stride }
+    }
+
+    // Offset of sample `index` within the pixel, both in [0, 1)
+    pub fn offset<R: Rng + ?Sized>(&self, index: u32, rng: &mut R) -> (f64, f64) {
+        let cells = self.n * self.n;
+        if index >= cells {
+            return (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+        }
+
+        let cell = (index as u64 * self.stride as u64 % cells as u64) as u32;
+        let (cx, cy) = (cell % self.n, cell / self.n);
+        (
+            (cx as f64 + rng.gen_range(0.0..1.0)) / self.n as f64,
+            (cy as f64 + rng.gen_range(0.0..1.0)) / self.n as f64,
+        )
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraBuilder;
+    use crate::la::assert_vec3_eq;
+    use crate::la::{Point3, Vec3};
+    use crate::objects::{
+        BoxObj, Dielectric, DiffuseLight, HittableList, Lambertian, Metal, Plane, Sphere, XyRect,
+        XzRect,
+    };
+    use crate::texture::CheckerTexture;
+
+    fn black() -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // The three-sphere demo scene on a big ground sphere
+    fn demo_world() -> HittableList {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -100.5, -1.0),
+            100.0,
+            Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0))),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(1.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 0.3)),
+        )));
+        world
+    }
+
+    fn camera_looking_at(look_from: Point3, look_at: Point3, up: Vec3) -> Camera {
+        Camera::new(
+            &look_from,
+            &look_at,
+            &up,
+            60.0,
+            16.0 / 9.0,
+            0.1,
+            (look_from - look_at).length(),
+        )
+    }
+
+    fn demo_camera() -> Camera {
+        camera_looking_at(
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        )
+    }
+
+    // Few pixels and samples, so tests stay fast in debug builds
+    fn small_renderer(camera: Camera, world: HittableList) -> Renderer {
+        let settings = RenderSettings {
+            image_width: 16,
+            image_height: 9,
+            samples_per_pixel: 4,
+            min_samples_per_pixel: 4,
+            max_depth: 8,
+            ..RenderSettings::default()
+        };
+        let mut renderer = Renderer::new(camera, Arc::new(world), settings);
+        renderer.show_progress = false;
+        renderer
+    }
+
+    fn demo_renderer() -> Renderer {
+        small_renderer(demo_camera(), demo_world())
+    }
+
+    #[test]
+    fn same_seed_renders_identical_images() {
+        let mut renderer = demo_renderer();
+        renderer.settings.seed = 7;
+        let first = renderer.render();
+        assert_eq!(first.as_raw(), renderer.render().as_raw());
+
+        renderer.settings.seed = 8;
+        assert_ne!(first.as_raw(), renderer.render().as_raw());
+    }
+
+    #[test]
+    fn checkered_ground_renders_both_colors() {
+        let checker = CheckerTexture::from_colors(black(), Color::new(1.0, 1.0, 1.0), 2.0);
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -100.5, 0.0),
+            100.0,
+            Arc::new(Lambertian::from_texture(Arc::new(checker))),
+        )));
+        let camera = camera_looking_at(
+            Point3::new(0.0, 3.0, 0.0),
+            Point3::new(0.0, -0.5, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+
+        let image = small_renderer(camera, world).render();
+        let darkest = image.pixels().map(|p| p[0]).min().unwrap();
+        let brightest = image.pixels().map(|p| p[0]).max().unwrap();
+        assert!(brightest - darkest > 100, "{} to {}", darkest, brightest);
+    }
+
+    #[test]
+    fn diffuse_light_shines_without_a_sky() {
+        let light = Sphere::new(
+            Point3::new(0.0, 0.0, -2.0),
+            0.5,
+            Arc::new(DiffuseLight::from_color(Color::new(4.0, 4.0, 4.0))),
+        );
+        let mut rng = SmallRng::seed_from_u64(0);
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let color = ray_color(&r, &light, &black(), None, None, 0.001, 8, &mut rng);
+        assert_eq!(color, Color::new(4.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn black_background_without_lights_renders_black() {
+        let mut renderer = demo_renderer();
+        renderer.background = Arc::new(black());
+        let image = renderer.render();
+        assert!(image.pixels().all(|p| p.0 == [0, 0, 0]));
+    }
+
+    #[test]
+    fn sky_blends_white_to_blue_on_a_miss() {
+        let empty = HittableList::new();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut miss = |direction: Vec3| {
+            let r = Ray::new(Point3::new(0.0, 0.0, 0.0), direction);
+            ray_color(&r, &empty, &Sky, None, None, 0.001, 8, &mut rng)
+        };
+
+        let up = miss(Vec3::new(0.0, 1.0, 0.0));
+        assert!((up - Color::new(0.5, 0.7, 1.0)).length() < 1e-12);
+        let down = miss(Vec3::new(0.0, -1.0, 0.0));
+        assert!((down - Color::new(1.0, 1.0, 1.0)).length() < 1e-12);
+        let level = miss(Vec3::new(1.0, 0.0, 0.0));
+        assert!((level - Color::new(0.75, 0.85, 1.0)).length() < 1e-12);
+    }
+
+    // ray_color as it was before it became a loop
+    fn recursive_ray_color(
+        r: &Ray,
+        world: &dyn Hittable,
+        background: &dyn Background,
+        depth: u32,
+        rng: &mut SmallRng,
+    ) -> Color {
+        if depth == 0 {
+            return black();
+        }
+        let rec = match world.hit(r, 0.001, f64::INFINITY) {
+            Some(rec) => rec,
+            None => return background.color(&r.unit_direction()),
+        };
+
+        let emitted = rec.mat.emitted(r, &rec);
+        let srec = match rec.mat.scatter(r, &rec, rng) {
+            Some(srec) => srec,
+            None => return emitted,
+        };
+        match srec.kind {
+            ScatterKind::Specular(scattered) => {
+                emitted
+                    + srec.attenuation
+                        * recursive_ray_color(&scattered, world, background, depth - 1, rng)
+            }
+            ScatterKind::Scattering(pdf) => {
+                let scattered = Ray::new_timed(rec.p, pdf.generate(rng), r.time());
+                let pdf_value = pdf.value(&scattered.direction());
+                if pdf_value <= 0.0 {
+                    return emitted;
+                }
+                let weight = rec.mat.scattering_pdf(r, &rec, &scattered) / pdf_value;
+                emitted
+                    + srec.attenuation
+                        * weight
+                        * recursive_ray_color(&scattered, world, background, depth - 1, rng)
+            }
+        }
+    }
+
+    #[test]
+    fn iterative_ray_color_matches_recursive() {
+        let world = demo_world();
+        let camera = demo_camera();
+
+        for i in 0..50 {
+            let mut rng = SmallRng::seed_from_u64(i);
+            let r = camera.get_ray_rng(rng.gen(), rng.gen(), &mut rng);
+            let mut loop_rng = SmallRng::seed_from_u64(1000 + i);
+            let mut recursive_rng = loop_rng.clone();
+
+            let iterative = ray_color(&r, &world, &Sky, None, None, 0.001, 8, &mut loop_rng);
+            let recursive = recursive_ray_color(&r, &world, &Sky, 8, &mut recursive_rng);
+            assert!(
+                (iterative - recursive).length() < 1e-9,
+                "{:?} {:?}",
+                iterative,
+                recursive
+            );
+        }
+    }
+
+    #[test]
+    fn adaptive_sampling_stops_early_only_on_flat_pixels() {
+        let gray = Color::new(0.5, 0.5, 0.5);
+        let (sum, n) = sample_pixel(|_| gray, 4, 64, 0.01);
+        assert_eq!(n, 4);
+        assert!((sum - 4.0 * gray).length() < 1e-12);
+
+        // Alternating black and white never gets its error below the tolerance
+        let mut white = false;
+        let noisy = |_| {
+            white = !white;
+            if white {
+                Color::new(1.0, 1.0, 1.0)
+            } else {
+                black()
+            }
+        };
+        let (_, n) = sample_pixel(noisy, 4, 64, 0.01);
+        assert_eq!(n, 64);
+    }
+
+    #[test]
+    fn tiny_render_has_its_size_and_a_lit_center() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let mut renderer = small_renderer(demo_camera(), world);
+        renderer.settings.image_width = 4;
+        renderer.settings.image_height = 4;
+
+        let image = renderer.render();
+        assert_eq!(image.dimensions(), (4, 4));
+        assert_ne!(image.get_pixel(2, 2).0, [0, 0, 0]);
+    }
+
+    #[test]
+    fn sixteen_stratified_samples_fill_sixteen_cells() {
+        let stratifier = Stratifier::new(16, true);
+        let mut rng = SmallRng::seed_from_u64(3);
+        let mut cells: Vec<_> = (0..16)
+            .map(|index| {
+                let (x, y) = stratifier.offset(index, &mut rng);
+                ((x * 4.0) as u32, (y * 4.0) as u32)
+            })
+            .collect();
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 16);
+    }
+
+    #[test]
+    fn head_on_normal_maps_to_blue() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::new(black())),
+        );
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let color = debug_color(&r, &sphere, 0.001, RenderMode::Normals);
+        assert!((color - Color::new(0.5, 0.5, 1.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn tiles_render_the_same_image_as_rows() {
+        let mut renderer = demo_renderer();
+        renderer.settings.seed = 11;
+        let rows = renderer.render();
+        for tile_size in [1, 4, 32] {
+            assert_eq!(renderer.render_tiled(tile_size).as_raw(), rows.as_raw());
+        }
+    }
+
+    #[test]
+    fn hdr_keeps_values_above_one_until_tone_mapping() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(DiffuseLight::from_color(Color::new(4.0, 4.0, 4.0))),
+        )));
+        let mut renderer = small_renderer(demo_camera(), world);
+        renderer.background = Arc::new(black());
+
+        let hdr = renderer.render_hdr();
+        assert_eq!(hdr.get_pixel(8, 4), Color::new(4.0, 4.0, 4.0));
+        assert_eq!(renderer.tone_map(&hdr).get_pixel(8, 4).0, [255; 3]);
+
+        // Reinhard maps 4 to 4 / (1 + 4), then gamma 2 takes its square root
+        renderer.settings.tone_map = ToneMap::Reinhard;
+        assert_eq!(renderer.tone_map(&hdr).get_pixel(8, 4).0, [228; 3]);
+    }
+
+    #[test]
+    fn russian_roulette_keeps_the_average_brightness() {
+        let world = demo_world();
+        let mut rng = SmallRng::seed_from_u64(9);
+        let r = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.1, -0.2, -1.0));
+        let samples = 20_000;
+
+        let mut with_roulette = black();
+        let mut without = black();
+        for _ in 0..samples {
+            with_roulette += ray_color(&r, &world, &Sky, None, None, 0.001, 50, &mut rng);
+            without += recursive_ray_color(&r, &world, &Sky, 50, &mut rng);
+        }
+
+        let brightness = |sum: Color| (sum.x() + sum.y() + sum.z()) / (3.0 * samples as f64);
+        let (with_roulette, without) = (brightness(with_roulette), brightness(without));
+        assert!(
+            (with_roulette - without).abs() < 0.02 * without,
+            "{} vs {}",
+            with_roulette,
+            without
+        );
+    }
+
+    // A closed unit box lit by a small, bright square under its ceiling, and
+    // the light
+    fn cornell_box() -> (HittableList, Arc<dyn Hittable>) {
+        let light: Arc<dyn Hittable> = Arc::new(XzRect::new(
+            0.45,
+            0.55,
+            0.45,
+            0.55,
+            0.999,
+            Arc::new(DiffuseLight::from_color(Color::new(60.0, 60.0, 60.0))),
+        ));
+
+        let mut world = HittableList::new();
+        world.add(Arc::new(BoxObj::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 1.0, 1.0),
+            Arc::new(Lambertian::new(Color::new(0.73, 0.73, 0.73))),
+        )));
+        world.add(light.clone());
+        (world, light)
+    }
+
+    // Mean and variance of the brightness of `samples` paths along `r`
+    fn brightness_stats(
+        r: &Ray,
+        world: &dyn Hittable,
+        lights: Option<&dyn Hittable>,
+        samples: u32,
+    ) -> (f64, f64) {
+        let mut rng = SmallRng::seed_from_u64(2);
+        let values: Vec<f64> = (0..samples)
+            .map(|_| {
+                let color = ray_color(r, world, &black(), lights, None, 0.001, 8, &mut rng);
+                (color.x() + color.y() + color.z()) / 3.0
+            })
+            .collect();
+        let mean = values.iter().sum::<f64>() / samples as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / samples as f64;
+        (mean, variance)
+    }
+
+    #[test]
+    fn sampling_the_light_converges_faster_in_a_cornell_box() {
+        let (world, light) = cornell_box();
+        let r = Ray::new(Point3::new(0.5, 0.5, 0.9), Vec3::new(0.0, -1.0, -0.8));
+
+        let samples = 20_000;
+        let (uniform_mean, uniform_variance) = brightness_stats(&r, &world, None, samples);
+        let (light_mean, light_variance) =
+            brightness_stats(&r, &world, Some(light.as_ref()), samples);
+
+        // Same answer, within four standard errors
+        let standard_error = ((light_variance + uniform_variance) / samples as f64).sqrt();
+        assert!(
+            (light_mean - uniform_mean).abs() < 4.0 * standard_error,
+            "{} vs {}",
+            light_mean,
+            uniform_mean
+        );
+        // With less than half the variance, half the samples reach the same
+        // error
+        assert!(
+            light_variance < 0.5 * uniform_variance,
+            "{} vs {}",
+            light_variance,
+            uniform_variance
+        );
+    }
+
+    #[test]
+    fn lights_that_cannot_be_sampled_leave_the_material_to_scatter() {
+        let world = demo_world();
+        let plane = Plane::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Arc::new(DiffuseLight::from_color(Color::new(1.0, 1.0, 1.0))),
+        );
+        assert!(!plane.can_sample());
+
+        let r = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let trace = |lights: Option<&dyn Hittable>| {
+            let mut rng = SmallRng::seed_from_u64(4);
+            (0..100)
+                .map(|_| ray_color(&r, &world, &Sky, lights, None, 0.001, 8, &mut rng))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(trace(Some(&plane)), trace(None));
+    }
+
+    #[test]
+    fn resuming_a_checkpoint_matches_one_long_render() {
+        let path =
+            std::env::temp_dir().join(format!("ray_tracing_checkpoint_{}.bin", std::process::id()));
+        let with_samples = |samples_per_pixel| {
+            let mut renderer = demo_renderer();
+            renderer.settings.samples_per_pixel = samples_per_pixel;
+            renderer.settings.seed = 5;
+            renderer
+        };
+
+        let whole = with_samples(200).render_checkpointed().unwrap();
+
+        let mut first_half = with_samples(100);
+        first_half.checkpoint = Some(CheckpointConfig {
+            path: path.clone(),
+            interval: std::time::Duration::from_secs(3600),
+        });
+        first_half.render_checkpointed().unwrap();
+        let resumed = with_samples(200).resume_from(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(resumed.unwrap().pixels(), whole.pixels());
+    }
+
+    #[test]
+    fn progressive_passes_add_up_to_one_render() {
+        let mut renderer = demo_renderer();
+        renderer.settings.samples_per_pixel = 6;
+        renderer.settings.min_samples_per_pixel = 6;
+        let mut passes = Vec::new();
+        let progressive = renderer.render_progressive(6, |image, pass| {
+            assert_eq!(image.dimensions(), (16, 9));
+            passes.push(pass);
+        });
+
+        assert_eq!(passes, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(progressive.pixels(), renderer.render_hdr().pixels());
+    }
+
+    #[test]
+    fn larger_t_min_removes_shadow_acne() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )));
+        let dark_fraction = |t_min| {
+            let camera = CameraBuilder::new(Point3::new(0.0, 2.0, 3.0), Point3::new(0.0, 0.0, 0.0))
+                .v_fov(40.0)
+                .build();
+            let mut renderer = small_renderer(camera, world.clone());
+            renderer.settings.t_min = t_min;
+            let image = renderer.render();
+            let dark = image.pixels().filter(|p| p.0[0] < 64).count();
+            dark as f64 / image.pixels().len() as f64
+        };
+
+        let acne = dark_fraction(0.0);
+        let clean = dark_fraction(0.001);
+        assert!(acne > 0.2, "{}", acne);
+        assert!(clean < 0.01, "{}", clean);
+    }
+
+    #[test]
+    fn camera_aspect_ratio_follows_the_image_size() {
+        let renderer_for = |image_width, image_height| {
+            let settings = RenderSettings {
+                image_width,
+                image_height,
+                ..RenderSettings::default()
+            };
+            let pinhole =
+                CameraBuilder::new(Point3::new(0.0, 0.0, 1.0), Point3::new(0.0, 0.0, -1.0)).build();
+            Renderer::new(pinhole, Arc::new(HittableList::new()), settings)
+        };
+
+        let square = renderer_for(100, 100);
+        assert!((square.camera.aspect_ratio() - 1.0).abs() < 1e-12);
+
+        // Pinhole rays to the viewport corners span its two edges
+        let wide = renderer_for(200, 100);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut corner = |s, t| wide.camera.get_ray_rng(s, t, &mut rng).direction();
+        let origin = corner(0.0, 0.0);
+        let horizontal = corner(1.0, 0.0) - origin;
+        let vertical = corner(0.0, 1.0) - origin;
+        assert!((horizontal.length() - 2.0 * vertical.length()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn two_by_two_render_counts_four_primary_rays() {
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+        let mut renderer = demo_renderer();
+        renderer.world = Arc::new(sphere);
+        renderer.settings.image_width = 2;
+        renderer.settings.image_height = 2;
+        renderer.settings.samples_per_pixel = 1;
+        renderer.settings.min_samples_per_pixel = 1;
+
+        let (_, stats) = renderer.render_with_stats();
+        if !cfg!(feature = "stats") {
+            assert_eq!(stats, RenderStats::default());
+            return;
+        }
+        assert_eq!(stats.primary_rays, 4);
+        // Every ray, primary or scattered, tests the sphere once
+        assert_eq!(stats.hit_calls, stats.primary_rays + stats.scatter_rays);
+        // Counts start over with each render
+        assert_eq!(renderer.render_with_stats().1, stats);
+    }
+
+    #[test]
+    fn box_heatmap_is_brighter_where_two_boxes_overlap() {
+        let mut world = HittableList::new();
+        for x in [-0.3, 0.3] {
+            world.add(Arc::new(Sphere::new(
+                Point3::new(x, 0.0, -2.0),
+                0.5,
+                Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            )));
+        }
+        let mut renderer = small_renderer(demo_camera(), world);
+        renderer.settings.image_width = 64;
+        renderer.settings.image_height = 36;
+        renderer.settings.mode = RenderMode::BoxHeatmap { max: 2 };
+        let image = renderer.render();
+
+        // Column 32 looks through both boxes, 27 only through the left one
+        // and 2 through neither
+        let overlap = image.get_pixel(32, 18).0[0];
+        let single = image.get_pixel(27, 18).0[0];
+        let outside = image.get_pixel(2, 18).0[0];
+        assert_eq!(overlap, 255);
+        assert!(
+            overlap > single && single > outside,
+            "{} {} {}",
+            overlap,
+            single,
+            outside
+        );
+    }
+
+    #[test]
+    fn center_pixel_passes_face_the_camera_at_the_hit_distance() {
+        // Camera at z = 1, sphere surface facing it at z = -2
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, -3.0),
+            1.0,
+            Arc::new(Lambertian::new(Color::new(0.2, 0.4, 0.6))),
+        );
+        let mut world = HittableList::new();
+        world.add(Arc::new(sphere));
+        let mut camera = demo_camera();
+        camera.set_aperture(0.0);
+        let mut renderer = small_renderer(camera, world);
+        renderer.settings.image_width = 32;
+        renderer.settings.image_height = 32;
+        renderer.camera.set_aspect_ratio(1.0);
+
+        // Pixel (15, 16) straddles the middle of the image
+        let passes = renderer.render_passes();
+        let normal = passes.normal.get_pixel(15, 16);
+        assert!(normal.z() > 0.999, "{:?}", normal);
+        let depth = passes.depth[16 * 32 + 15];
+        assert!((depth - 3.0).abs() < 5e-3, "{}", depth);
+        assert_vec3_eq!(
+            passes.albedo.get_pixel(15, 16),
+            Color::new(0.2, 0.4, 0.6),
+            1e-6
+        );
+    }
+
+    #[test]
+    fn one_and_eight_threads_encode_identical_pngs() {
+        let renderer = demo_renderer();
+        let pngs_on = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let images = pool.install(|| [renderer.render(), renderer.render_tiled(4)]);
+            images.map(|image| {
+                let mut png = Vec::new();
+                image
+                    .write_to(
+                        &mut std::io::Cursor::new(&mut png),
+                        image::ImageOutputFormat::Png,
+                    )
+                    .unwrap();
+                png
+            })
+        };
+
+        let [rows, tiles] = pngs_on(1);
+        assert_eq!(rows, tiles);
+        assert_eq!(pngs_on(8), [rows, tiles]);
+    }
+
+    #[test]
+    fn crop_traces_only_its_window() {
+        let mut renderer = demo_renderer();
+        renderer.settings.image_width = 100;
+        renderer.settings.image_height = 100;
+        renderer.settings.samples_per_pixel = 1;
+        renderer.settings.min_samples_per_pixel = 1;
+        let full = renderer.render_hdr();
+        renderer.settings.crop = Some((40, 50, 50, 60));
+        let cropped = renderer.render_hdr();
+
+        for y in 0..100 {
+            for x in 0..100 {
+                let pixel = cropped.get_pixel(x, y);
+                if (40..50).contains(&x) && (50..60).contains(&y) {
+                    assert_eq!(pixel, full.get_pixel(x, y));
+                } else {
+                    assert_eq!(pixel, Color::new(0.0, 0.0, 0.0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clay_override_shades_metal_and_glass_alike() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(-1.0, 0.0, -3.0),
+            0.5,
+            Arc::new(Metal::new(Color::new(0.9, 0.2, 0.2), 0.0)),
+        )));
+        world.add(Arc::new(Sphere::new(
+            Point3::new(1.0, 0.0, -3.0),
+            0.5,
+            Arc::new(Dielectric::new(1.5)),
+        )));
+        let clay = Lambertian::new(Color::new(0.3, 0.6, 0.4));
+        // Under a uniform white sky a convex diffuse object reflects exactly
+        // its albedo, none of its bounces can hit it again
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mut rng = SmallRng::seed_from_u64(14);
+
+        for x in [-1.0, 1.0] {
+            let r = Ray::new(Point3::new(x, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+            let plain = ray_color(&r, &world, &white, None, None, 0.001, 8, &mut rng);
+            assert!(!plain.approx_eq(&Color::new(0.3, 0.6, 0.4), 0.05));
+            let shaded = ray_color(&r, &world, &white, None, Some(&clay), 0.001, 8, &mut rng);
+            assert_vec3_eq!(shaded, Color::new(0.3, 0.6, 0.4), 1e-12);
+        }
+    }
+
+    #[test]
+    fn firefly_clamp_bounds_bright_samples() {
+        // One firefly among dim samples barely moves the clamped mean
+        let samples = [Color::new(0.5, 0.5, 0.5); 15]
+            .into_iter()
+            .chain([Color::new(1000.0, 1000.0, 1000.0)]);
+        let total = samples.fold(Color::new(0.0, 0.0, 0.0), |total, c| {
+            total + clamp_luminance(c, 10.0)
+        });
+        let mean = total / 16.0;
+        assert!(mean.luminance() <= (15.0 * 0.5 + 10.0) / 16.0 + 1e-12);
+
+        // Through the renderer: every sample sees a sky of luminance 1000
+        let mut renderer = small_renderer(demo_camera(), HittableList::new());
+        renderer.settings.firefly_clamp = Some(10.0);
+        renderer.background = Arc::new(Color::new(1000.0, 1000.0, 1000.0));
+        for pixel in renderer.render_hdr().pixels() {
+            let luminance =
+                Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64).luminance();
+            assert!((luminance - 10.0).abs() < 1e-3, "{}", luminance);
+        }
+    }
+
+    #[test]
+    fn bvh_compiled_world_renders_like_the_plain_list() {
+        // The demo spheres on a ground plane instead of a ground sphere
+        let world = || {
+            let mut world = demo_world();
+            world.remove(0);
+            world.add(Arc::new(Plane::new(
+                Point3::new(0.0, -0.5, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+                Arc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0))),
+            )));
+            world
+        };
+        let render = |bvh| {
+            let mut renderer = demo_renderer();
+            renderer.settings.bvh = bvh;
+            renderer.set_world(world());
+            renderer.render()
+        };
+
+        assert!(!RenderSettings::default().bvh);
+        assert_eq!(render(true), render(false));
+    }
+
+    #[test]
+    fn picking_the_center_finds_the_front_sphere() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5))),
+        )));
+        let mut renderer = small_renderer(demo_camera(), world);
+        renderer.camera.set_aperture(0.0);
+        let (distance, p) = renderer.pick(0.5, 0.5).unwrap();
+        // The camera at z = 1 looks at the sphere's front at z = -0.5
+        assert!((distance - 1.5).abs() < 1e-9, "{}", distance);
+        assert_vec3_eq!(p, Point3::new(0.0, 0.0, -0.5), 1e-9);
+
+        // Straight up over the sphere there's only sky
+        assert!(renderer.pick(0.5, 1.0).is_none());
+    }
+
+    #[test]
+    fn dispersive_paths_keep_one_color_past_other_bounces() {
+        // Through a glass sphere to a mirror and back through the glass. If
+        // the glass picked a new color on the way back, two times in three
+        // the path would come out black.
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(
+            Point3::new(0.0, 0.0, -3.0),
+            0.5,
+            Arc::new(Dielectric::new_dispersive(1.50, 1.52, 1.56)),
+        )));
+        world.add(Arc::new(XyRect::new(
+            -5.0,
+            5.0,
+            -5.0,
+            5.0,
+            -5.0,
+            Arc::new(Metal::new(Color::new(0.9, 0.9, 0.9), 0.0)),
+        )));
+        let white = Color::new(1.0, 1.0, 1.0);
+        let r = Ray::new(Point3::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rng = SmallRng::seed_from_u64(21);
+
+        for _ in 0..500 {
+            let color = ray_color(&r, &world, &white, None, None, 0.001, 16, &mut rng);
+            let lit = (0..3).filter(|&c| color[c] > 0.0).count();
+            assert_eq!(lit, 1, "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn one_ev_doubles_the_linear_value_and_zero_keeps_it() {
+        let mut hdr = HdrImage::new(2, 2);
+        let color = Color::new(0.25, 0.125, 0.0);
+        hdr.put_pixel(0, 0, color);
+        let exposed = |exposure_ev| {
+            let settings = RenderSettings {
+                exposure_ev,
+                ..RenderSettings::default()
+            };
+            assert_eq!(settings.exposure(), 2f64.powf(exposure_ev));
+            hdr.exposed(settings.exposure()).get_pixel(0, 0)
+        };
+
+        assert_eq!(exposed(0.0), color);
+        assert_eq!(exposed(1.0), 2.0 * color);
+        assert_eq!(exposed(-1.0), 0.5 * color);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::camera::{Camera, CameraBuilder};
+use crate::la::{Color, Point3, Vec3};
+use crate::objects::{
+    Coated, Dielectric, DiffuseLight, HittableList, Lambertian, Material, Metal, Quad, Sphere,
+    XyRect, XzRect, YzRect,
+};
+use crate::renderer::RenderSettings;
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A scene file looks like:
+//
+// {
+//     "camera": { "look_from": [-3, 1, 2], "look_at": [0, 0, -1], "v_fov": 30 },
+//     "settings": { "image_width": 400, "image_height": 225 },
+//     "objects": [
+//         {
+//             "type": "sphere",
+//             "center": [0, 0, -1],
+//             "radius": 0.5,
+//             "material": { "type": "lambertian", "albedo": [0.1, 0.2, 0.5] }
+//         },
+//         {
+//             "type": "xz_rect",
+//             "x0": -1, "x1": 1, "z0": -2, "z1": 0, "k": 2,
+//             "material": { "type": "diffuse_light", "color": [1, 1, 1], "intensity": 4 }
+//         }
+//     ]
+// }
+//
+// Lambertians take either an "albedo" color or a "texture", which is one of
+// { "type": "checker", "odd": [..], "even": [..], "scale": 10 } (add
+// "uv": true to lay the checks out over the surface's (u, v) instead),
+// { "type": "image", "path": "earth.png" } or { "type": "noise", "scale": 4 }.
+// Relative image paths are resolved against the scene file's directory.
+// Diffuse lights shine from both sides unless given "two_sided": false.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraDesc,
+    #[serde(default)]
+    settings: RenderSettings,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: Point3,
+    look_at: Point3,
+    #[serde(default = "default_up")]
+    up: Vec3,
+    v_fov: f64,
+    #[serde(default)]
+    aperture: f64,
+    // Defaults to the distance between look_from and look_at
+    focus_dist: Option<f64>,
+    #[serde(default)]
+    time0: f64,
+    #[serde(default)]
+    time1: f64,
+}
+
+fn default_up() -> Vec3 {
+    Vec3::new(0.0, 1.0, 0.0)
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: MaterialDesc,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialDesc,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialDesc,
+    },
+    // Parallelogram with corner `q` and edges `u` and `v`
+    Quad {
+        q: Point3,
+        u: Vec3,
+        v: Vec3,
+        material: MaterialDesc,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialDesc,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: Option<Color>,
+        texture: Option<TextureDesc>,
+    },
+    Metal {
+        albedo: Color,
+        roughness: f64,
+    },
+    Dielectric {
+        ior: f64,
+        tint: Option<Color>,
+        absorption: Option<Color>,
+        // Red, green and blue indices, replacing "ior"
+        dispersion: Option<[f64; 3]>,
+    },
+    Coated {
+        base: Color,
+        ior: f64,
+    },
+    DiffuseLight {
+        color: Color,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+        #[serde(default = "default_two_sided")]
+        two_sided: bool,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_two_sided() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Checker {
+        odd: Color,
+        even: Color,
+        #[serde(default = "default_checker_scale")]
+        scale: f64,
+        #[serde(default)]
+        uv: bool,
+    },
+    Image {
+        path: PathBuf,
+    },
+    Noise {
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+fn default_checker_scale() -> f64 {
+    10.0
+}
+
+pub fn load_scene<P: AsRef<Path>>(path: P) -> io::Result<(HittableList, Camera, RenderSettings)> {
+    let path = path.as_ref();
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    build_scene(&fs::read_to_string(path)?, base_dir)
+}
+
+// Malformed JSON, unknown object or material types, missing fields and
+// images smaller than 2x2 or without samples all come back as InvalidData
+// errors naming the problem (and its position, for JSON errors).
+// Image paths are taken relative to the working directory.
+pub fn parse_scene(source: &str) -> io::Result<(HittableList, Camera, RenderSettings)> {
+    build_scene(source, Path::new(""))
+}
+
+fn build_scene(
+    source: &str,
+    base_dir: &Path,
+) -> io::Result<(HittableList, Camera, RenderSettings)> {
+    let desc: SceneDesc = serde_json::from_str(source)?;
+    let settings = &desc.settings;
+    // The camera maps pixels over width - 1 and height - 1 steps
+    if settings.image_width < 2 || settings.image_height < 2 {
+        return Err(invalid_data(format!(
+            "image must be at least 2x2 pixels, got {}x{}",
+            settings.image_width, settings.image_height
+        )));
+    }
+    if settings.samples_per_pixel == 0 {
+        return Err(invalid_data(
+            "samples_per_pixel must be at least 1".to_string(),
+        ));
+    }
+
+    let mut world = HittableList::new();
+    for object in desc.objects {
+        match object {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => {
+                let mat = material.build(base_dir)?;
+                world.add(Arc::new(Sphere::new(center, radius, mat)));
+            }
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => {
+                let mat = material.build(base_dir)?;
+                world.add(Arc::new(XyRect::new(x0, x1, y0, y1, k, mat)));
+            }
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let mat = material.build(base_dir)?;
+                world.add(Arc::new(XzRect::new(x0, x1, z0, z1, k, mat)));
+            }
+            ObjectDesc::Quad { q, u, v, material } => {
+                let mat = material.build(base_dir)?;
+                world.add(Arc::new(Quad::new(q, u, v, mat)));
+            }
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => {
+                let mat = material.build(base_dir)?;
+                world.add(Arc::new(YzRect::new(y0, y1, z0, z1, k, mat)));
+            }
+        }
+    }
+
+    let c = desc.camera;
+    let mut camera = CameraBuilder::new(c.look_from, c.look_at)
+        .up(c.up)
+        .v_fov(c.v_fov)
+        .aspect_ratio(desc.settings.aspect_ratio())
+        .aperture(c.aperture)
+        .shutter(c.time0, c.time1);
+    if let Some(focus_dist) = c.focus_dist {
+        camera = camera.focus_dist(focus_dist);
+    }
+    let camera = camera.build();
+
+    Ok((world, camera, desc.settings))
+}
+
+impl MaterialDesc {
+    fn build(self, base_dir: &Path) -> io::Result<Arc<dyn Material>> {
+        Ok(match self {
+            MaterialDesc::Lambertian { albedo, texture } => match (albedo, texture) {
+                (Some(albedo), None) => Arc::new(Lambertian::new(albedo)),
+                (None, Some(texture)) => {
+                    Arc::new(Lambertian::from_texture(texture.build(base_dir)?))
+                }
+                _ => {
+                    return Err(invalid_data(
+                        "lambertian needs exactly one of \"albedo\" or \"texture\"".to_string(),
+                    ))
+                }
+            },
+            MaterialDesc::Metal { albedo, roughness } => Arc::new(Metal::new(albedo, roughness)),
+            MaterialDesc::Dielectric {
+                ior,
+                tint,
+                absorption,
+                dispersion,
+            } => Arc::new(Dielectric {
+                ior,
+                attenuation: tint.unwrap_or(Color::new(1.0, 1.0, 1.0)),
+                absorption: absorption.unwrap_or(Color::new(0.0, 0.0, 0.0)),
+                dispersion,
+            }),
+            MaterialDesc::Coated { base, ior } => Arc::new(Coated::new(base, ior)),
+            MaterialDesc::DiffuseLight {
+                color,
+                intensity,
+                two_sided,
+            } => {
+                let mut light = DiffuseLight::new(Arc::new(SolidColor::new(color)), intensity);
+                light.two_sided = two_sided;
+                Arc::new(light)
+            }
+        })
+    }
+}
+
+impl TextureDesc {
+    fn build(self, base_dir: &Path) -> io::Result<Arc<dyn Texture>> {
+        Ok(match self {
+            TextureDesc::Checker {
+                odd,
+                even,
+                scale,
+                uv: false,
+            } => Arc::new(CheckerTexture::from_colors(odd, even, scale)),
+            TextureDesc::Checker {
+                odd,
+                even,
+                scale,
+                uv: true,
+            } => Arc::new(CheckerTexture::uv_from_colors(odd, even, scale)),
+            TextureDesc::Image { path } => {
+                let path = base_dir.join(path);
+                let texture = ImageTexture::load(&path).map_err(|err| {
+                    invalid_data(format!("can't load texture {}: {}", path.display(), err))
+                })?;
+                Arc::new(texture)
+            }
+            TextureDesc::Noise { scale, seed } => Arc::new(NoiseTexture::new(scale, seed)),
+        })
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::Ray;
+    use rand::rngs::SmallRng;
+    use rand::SeedableRng;
+
+    const DEMO_SCENE: &str = r#"{
+        "camera": { "look_from": [0, 0, 1], "look_at": [0, 0, -1], "v_fov": 60 },
+        "settings": { "image_width": 16, "image_height": 9 },
+        "objects": [
+            {
+                "type": "sphere", "center": [0, -100.5, -1], "radius": 100,
+                "material": { "type": "lambertian", "albedo": [0.8, 0.8, 0.0] }
+            },
+            {
+                "type": "sphere", "center": [-1, 0, -1], "radius": 0.5,
+                "material": { "type": "dielectric", "ior": 1.5 }
+            },
+            {
+                "type": "sphere", "center": [0, 0, -1], "radius": 0.5,
+                "material": { "type": "lambertian", "albedo": [0.1, 0.2, 0.5] }
+            },
+            {
+                "type": "sphere", "center": [1, 0, -1], "radius": 0.5,
+                "material": { "type": "metal", "albedo": [0.8, 0.6, 0.2], "roughness": 0 }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn demo_scene_loads_from_json() {
+        let (world, camera, settings) = parse_scene(DEMO_SCENE).unwrap();
+        assert_eq!(world.objects().len(), 4);
+        assert_eq!(camera.origin(), Point3::new(0.0, 0.0, 1.0));
+        assert_eq!((settings.image_width, settings.image_height), (16, 9));
+    }
+
+    #[test]
+    fn degenerate_images_are_invalid_data() {
+        for settings in [
+            r#""image_width": 0"#,
+            r#""image_height": 1"#,
+            r#""samples_per_pixel": 0"#,
+        ] {
+            let source = DEMO_SCENE.replace(r#""image_width": 16"#, settings);
+            match parse_scene(&source) {
+                Err(err) => assert_eq!(err.kind(), io::ErrorKind::InvalidData),
+                Ok(_) => panic!("accepted {}", settings),
+            }
+        }
+    }
+
+    #[test]
+    fn emissive_rect_and_image_textured_sphere_load() {
+        let dir = std::env::temp_dir().join(format!("ray_tracing_scene_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        image::RgbImage::from_pixel(2, 2, image::Rgb([0, 255, 0]))
+            .save(dir.join("green.png"))
+            .unwrap();
+        let scene = r#"{
+            "camera": { "look_from": [0, 0, 1], "look_at": [0, 0, -1], "v_fov": 60 },
+            "objects": [
+                {
+                    "type": "xy_rect", "x0": -1, "x1": 1, "y0": -1, "y1": 1, "k": -3,
+                    "material": { "type": "diffuse_light", "color": [1, 1, 1], "intensity": 4 }
+                },
+                {
+                    "type": "sphere", "center": [0, 0, -1], "radius": 0.5,
+                    "material": {
+                        "type": "lambertian",
+                        "texture": { "type": "image", "path": "green.png" }
+                    }
+                }
+            ]
+        }"#;
+        fs::write(dir.join("scene.json"), scene).unwrap();
+        let loaded = load_scene(dir.join("scene.json"));
+        let missing = build_scene(scene, &dir.join("nowhere"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (world, _, _) = loaded.unwrap();
+        assert_eq!(world.objects().len(), 2);
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let r = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rect = world.objects()[0].hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rect.mat.emitted(&r, &rect), Color::new(4.0, 4.0, 4.0));
+        let sphere = world.objects()[1].hit(&r, 0.001, f64::INFINITY).unwrap();
+        let scatter = sphere.mat.scatter(&r, &sphere, &mut rng).unwrap();
+        assert_eq!(scatter.attenuation, Color::new(0.0, 1.0, 0.0));
+
+        match missing {
+            Err(err) => assert!(err.to_string().contains("green.png"), "{}", err),
+            Ok(_) => panic!("loaded a texture that doesn't exist"),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Ray and intersection counters for performance tuning. They're only
+// compiled in with the `stats` feature; without it the counting functions
+// are empty and every count reads as zero.
+//
+// Counts go to the current thread, and a StatsAccumulator collects what each
+// worker counted while working on one render, so renders running at the same
+// time don't see each other's rays.
+#[cfg(feature = "stats")]
+use std::cell::Cell;
+use std::sync::Mutex;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RenderStats {
+    // Camera rays, one per sample
+    pub primary_rays: u64,
+    // Rays continuing a path after a bounce
+    pub scatter_rays: u64,
+    // Ray-primitive intersection tests. Lists, BVH nodes, boxes, volumes
+    // and instance wrappers don't count themselves, only the primitives
+    // they end up testing do.
+    pub hit_calls: u64,
+}
+
+impl std::ops::AddAssign for RenderStats {
+    fn add_assign(&mut self, other: RenderStats) {
+        self.primary_rays += other.primary_rays;
+        self.scatter_rays += other.scatter_rays;
+        self.hit_calls += other.hit_calls;
+    }
+}
+
+#[cfg(feature = "stats")]
+thread_local! {
+    static COUNTS: Cell<RenderStats> = Cell::new(RenderStats::default());
+}
+
+#[cfg(feature = "stats")]
+fn bump(f: impl FnOnce(&mut RenderStats)) {
+    COUNTS.with(|counts| {
+        let mut stats = counts.get();
+        f(&mut stats);
+        counts.set(stats);
+    });
+}
+
+#[inline(always)]
+pub fn count_primary_ray() {
+    #[cfg(feature = "stats")]
+    bump(|stats| stats.primary_rays += 1);
+}
+
+#[inline(always)]
+pub fn count_scatter_ray() {
+    #[cfg(feature = "stats")]
+    bump(|stats| stats.scatter_rays += 1);
+}
+
+#[inline(always)]
+pub fn count_hit_call() {
+    #[cfg(feature = "stats")]
+    bump(|stats| stats.hit_calls += 1);
+}
+
+// Totals for one render
+#[derive(Debug, Default)]
+pub struct StatsAccumulator {
+    total: Mutex<RenderStats>,
+}
+
+impl StatsAccumulator {
+    // Runs `f` and adds what it counted on this thread. `f` mustn't hand
+    // work from other renders to this thread, so keep rayon calls out of it.
+    pub fn count<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "stats")]
+        {
+            let before = COUNTS.with(Cell::take);
+            let result = f();
+            let counted = COUNTS.with(|counts| counts.replace(before));
+            *self.total.lock().unwrap() += counted;
+            result
+        }
+        #[cfg(not(feature = "stats"))]
+        f()
+    }
+
+    pub fn total(&self) -> RenderStats {
+        *self.total.lock().unwrap()
+    }
+}
@@ -0,0 +1,254 @@
+use crate::la::{Color, Point3};
+use crate::perlin::Perlin;
+use image::{ImageResult, RgbImage};
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        self.color
+    }
+}
+
+// Which coordinates a checker pattern is laid out in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CheckerSpace {
+    // 3D cells of sin(scale * p) in world space, which stretch and pinch
+    // following the surface
+    World,
+    // A scale x scale grid over the (u, v) square, so it wraps evenly around
+    // spheres
+    Uv,
+}
+
+pub struct CheckerTexture {
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    scale: f64,
+    space: CheckerSpace,
+}
+
+impl CheckerTexture {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f64) -> CheckerTexture {
+        CheckerTexture::with_space(odd, even, scale, CheckerSpace::World)
+    }
+
+    pub fn with_space(
+        odd: Arc<dyn Texture>,
+        even: Arc<dyn Texture>,
+        scale: f64,
+        space: CheckerSpace,
+    ) -> CheckerTexture {
+        CheckerTexture {
+            odd,
+            even,
+            scale,
+            space,
+        }
+    }
+
+    pub fn from_colors(odd: Color, even: Color, scale: f64) -> CheckerTexture {
+        CheckerTexture::new(
+            Arc::new(SolidColor::new(odd)),
+            Arc::new(SolidColor::new(even)),
+            scale,
+        )
+    }
+
+    pub fn uv_from_colors(odd: Color, even: Color, scale: f64) -> CheckerTexture {
+        CheckerTexture::with_space(
+            Arc::new(SolidColor::new(odd)),
+            Arc::new(SolidColor::new(even)),
+            scale,
+            CheckerSpace::Uv,
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let odd = match self.space {
+            CheckerSpace::World => {
+                let sines = f64::sin(self.scale * p.x())
+                    * f64::sin(self.scale * p.y())
+                    * f64::sin(self.scale * p.z());
+                sines < 0.0
+            }
+            CheckerSpace::Uv => {
+                let cells = (self.scale * u).floor() as i64 + (self.scale * v).floor() as i64;
+                cells.rem_euclid(2) == 1
+            }
+        };
+
+        if odd {
+            self.odd.value(u, v, p)
+        } else {
+            self.even.value(u, v, p)
+        }
+    }
+}
+
+pub struct ImageTexture {
+    image: Option<RgbImage>,
+}
+
+impl ImageTexture {
+    // Falls back to debug magenta if the image can't be loaded
+    pub fn new<P: AsRef<Path>>(path: P) -> ImageTexture {
+        ImageTexture::load(path.as_ref()).unwrap_or_else(|err| {
+            eprintln!(
+                "Could not load texture {}: {}",
+                path.as_ref().display(),
+                err
+            );
+            ImageTexture { image: None }
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<ImageTexture> {
+        let image = image::open(path)?.into_rgb8();
+        Ok(ImageTexture { image: Some(image) })
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        // Debug magenta makes a missing texture obvious in the render
+        let image = match &self.image {
+            Some(image) if image.width() > 0 && image.height() > 0 => image,
+            _ => return Color::new(1.0, 0.0, 1.0),
+        };
+
+        // Nearest sample, v = 0 is the bottom row of the image
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
+        let i = ((u * image.width() as f64) as u32).min(image.width() - 1);
+        let j = ((v * image.height() as f64) as u32).min(image.height() - 1);
+
+        let pixel = image.get_pixel(i, j);
+        Color::new(
+            pixel[0] as f64 / u8::MAX as f64,
+            pixel[1] as f64 / u8::MAX as f64,
+            pixel[2] as f64 / u8::MAX as f64,
+        )
+    }
+}
+
+pub struct NoiseTexture {
+    noise: Perlin,
+    scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, seed: u64) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::new(seed),
+            scale,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    // Marble: sine bands along z, phase-shifted by turbulence
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let phase = self.scale * p.z() + 10.0 * self.noise.turb(p, 7);
+        Color::new(1.0, 1.0, 1.0) * 0.5 * (1.0 + phase.sin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::la::{Ray, Vec3};
+    use crate::objects::{Hittable, Lambertian, Sphere};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn image_texture_corners_sample_corner_pixels() {
+        let mut image = RgbImage::new(2, 2);
+        image.put_pixel(0, 0, image::Rgb([255, 0, 0]));
+        image.put_pixel(1, 0, image::Rgb([0, 255, 0]));
+        image.put_pixel(0, 1, image::Rgb([0, 0, 255]));
+        image.put_pixel(1, 1, image::Rgb([255, 255, 255]));
+        let path =
+            std::env::temp_dir().join(format!("ray_tracing_texture_{}.png", std::process::id()));
+        image.save(&path).unwrap();
+        let texture = ImageTexture::new(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // v = 0 is the bottom row
+        let p = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(texture.value(0.0, 1.0, &p), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(texture.value(1.0, 1.0, &p), Color::new(0.0, 1.0, 0.0));
+        assert_eq!(texture.value(0.0, 0.0, &p), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(texture.value(1.0, 0.0, &p), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn missing_image_samples_magenta() {
+        let texture = ImageTexture::new("no/such/texture.png");
+        let p = Point3::new(0.0, 0.0, 0.0);
+        assert_eq!(texture.value(0.5, 0.5, &p), Color::new(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn uv_checker_alternates_with_u_and_world_checker_follows_position() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        // Hits around the equator of a unit sphere, half a cell apart from
+        // the cell edges and one cell apart from each other in u
+        let sphere = Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(Lambertian::new(white)),
+        );
+        let hits: Vec<_> = (0..16)
+            .map(|i| {
+                let phi = 2.0 * PI * (i as f64 + 0.5) / 16.0;
+                let r = Ray::new(
+                    Point3::new(0.0, 0.0, 0.0),
+                    Vec3::new(phi.cos(), 0.1, phi.sin()),
+                );
+                sphere.hit(&r, 0.001, f64::INFINITY).unwrap()
+            })
+            .collect();
+
+        let uv = CheckerTexture::uv_from_colors(black, white, 16.0);
+        let colors: Vec<_> = hits
+            .iter()
+            .map(|rec| uv.value(rec.u, rec.v, &rec.p))
+            .collect();
+        for pair in colors.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+
+        // The world checker only looks at p
+        let world = CheckerTexture::from_colors(black, white, 4.0);
+        for rec in &hits {
+            assert_eq!(
+                world.value(rec.u, rec.v, &rec.p),
+                world.value(0.0, 0.0, &rec.p)
+            );
+            assert_eq!(
+                uv.value(rec.u, rec.v, &rec.p),
+                uv.value(rec.u, rec.v, &Point3::new(0.0, 0.0, 0.0))
+            );
+        }
+        let along_x = |x| world.value(0.5, 0.5, &Point3::new(x, 0.3, 0.3));
+        assert_ne!(along_x(0.3), along_x(-0.3));
+    }
+}